@@ -3,8 +3,9 @@
 mod s1_box;
 mod s2_custom_smart_pointer;
 mod s3_cell;
-mod s4_refcell;
-mod s5_rc;
+pub mod s4_refcell;
+pub mod s5_rc;
+mod s6_arc;
 
 #[macro_export]
 macro_rules! delim {
@@ -1,3 +1,7 @@
+// The exercises that don't compile yet are parked behind a `skip` feature that
+// is deliberately never declared.
+#![allow(unexpected_cfgs)]
+
 /**
  * Using `Box<T>` to Store Data on the Heap:
  *
@@ -149,6 +153,8 @@ fn owning_trait_object() {
     println!("{}", animal.sound());
 }
 
+// Spelling out the derefs is the point of these demos.
+#[allow(clippy::explicit_auto_deref)]
 #[test]
 fn what_if_box_is_not_smart_pointer() {
     let b = Box::new(String::from("Hello"));
@@ -158,6 +164,7 @@ fn what_if_box_is_not_smart_pointer() {
     let inner_inner: &str = &**b; // Box<String> => String => &str
 }
 
+#[allow(clippy::explicit_auto_deref)]
 #[test]
 fn what_is_going_on_here() {
     use std::ops::Deref;
@@ -168,6 +175,7 @@ fn what_is_going_on_here() {
     let inner: &str = &*b; // &Box<String> => &str ???
 }
 
+#[allow(clippy::explicit_auto_deref, clippy::borrow_deref_ref)]
 #[test]
 fn auto_deref() {
     use std::ops::Deref;
@@ -1,3 +1,7 @@
+// The exercises that don't compile yet are parked behind a `skip` feature that
+// is deliberately never declared.
+#![allow(unexpected_cfgs)]
+
 /**
  * Defining Custom Smart Pointers
  * 1. Creating a Smart Pointer by Using a Tuple Struct
@@ -6,17 +10,43 @@
  * 4. Running Code on Cleanup with the `Drop` Trait
  * 5. Enabling `Deref` Coercion with `AsRef`
  */
-use std::fmt::Debug;
+use std::{borrow::Borrow, fmt};
 
-#[derive(Debug)]
-struct MyBox<T: Debug>(T);
+// The derives only apply when `T` has the trait, so `MyBox` can hold any `T`.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct MyBox<T>(T);
 
-impl<T: Debug> MyBox<T> {
+impl<T> MyBox<T> {
     fn new(x: T) -> MyBox<T> {
         MyBox(x)
     }
 }
 
+impl<T: fmt::Display> fmt::Display for MyBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// Formats the address of the boxed value, like `{:p}` does for references.
+impl<T> fmt::Pointer for MyBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&&self.0, f)
+    }
+}
+
+impl<T> Borrow<T> for MyBox<T> {
+    fn borrow(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for MyBox<T> {
+    fn from(x: T) -> Self {
+        MyBox(x)
+    }
+}
+
 #[test]
 fn my_box_creation() {
     let mbox = MyBox::new(42);
@@ -24,7 +54,7 @@ fn my_box_creation() {
     println!("{:?}", mbox);
 }
 
-/**
+/*
  * Treating Smart Pointers Like Regular References with the `Deref` Trait:
  *
  * Implementing the `Deref` trait allows you to customize the behavior of the
@@ -115,9 +145,11 @@ fn cascading_drops_for_smart_pointer() {
     let mbox = MyBox::new(MyBox::new(String::from("Rust")));
 }
 
-/*
- * TODO: define `AsRef` for `MyBox`
- */
+impl<T> AsRef<T> for MyBox<T> {
+    fn as_ref(&self) -> &T {
+        &self.0
+    }
+}
 
 #[cfg(feature = "skip")]
 #[test]
@@ -128,7 +160,6 @@ fn as_ref_for_custom_smart_pointer() {
     println!("{}", ref_t);
 }
 
-#[cfg(feature = "skip")]
 #[test]
 fn as_ref_for_cascading_custom_smart_pointer() {
     let mbox = MyBox::new(MyBox::new(String::from("Rust")));
@@ -138,6 +169,30 @@ fn as_ref_for_cascading_custom_smart_pointer() {
     println!("{:?}", into_ref);
 }
 
+#[test]
+fn my_box_without_debug() {
+    let greet = MyBox::new(|name: &str| format!("Hello, {name}!"));
+
+    assert_eq!((greet.0)("Rust"), "Hello, Rust!");
+    println!("greet lives at {:p}", greet);
+}
+
+#[test]
+fn my_box_as_map_key() {
+    use std::collections::{BTreeMap, HashMap};
+
+    let mut counts = HashMap::new();
+    counts.insert(MyBox::new(String::from("apple")), 3);
+    counts.insert(MyBox::from(String::from("kiwi")), 1);
+    assert_eq!(counts.get(&String::from("apple")), Some(&3));
+
+    let sorted: BTreeMap<MyBox<i32>, &str> = [(MyBox(2), "two"), (MyBox(1), "one")].into();
+    assert_eq!(sorted.values().copied().collect::<Vec<_>>(), ["one", "two"]);
+
+    assert_eq!(format!("{}", MyBox::new(42)), "42");
+    assert_eq!(MyBox::<i32>::default(), MyBox(0));
+}
+
 /**
  * Example: `String` or `&str`
  *
@@ -162,6 +217,8 @@ mod as_ref_demos {
         // take_string(String::from("Rust")); // No type coercion
     }
 
+    // The `&String` argument shows that `Into<String>` takes it as well.
+    #[allow(clippy::needless_borrows_for_generic_args)]
     #[test]
     fn take_str_reference_and_string_reference_and_string1() {
         fn take_string(s: impl Into<String>) {
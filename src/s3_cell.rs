@@ -1,16 +1,30 @@
+use std::any::TypeId;
 use std::cell::UnsafeCell;
-use std::marker::PhantomData;
+use std::cmp::Ordering;
+use std::convert::Infallible;
+use std::fmt;
+use std::hint;
 use std::mem;
+use std::ops::Deref;
 use std::ptr;
-
-#[derive(Debug)]
-pub struct Cell<T> {
-    _phantom: PhantomData<T>,
+use std::sync::atomic::{self, AtomicBool, AtomicU16, AtomicU32, AtomicU64, AtomicU8};
+
+/// A mutable memory location.
+///
+/// `Cell<T>` is `Send` if `T` is `Send`, but it is never `Sync`: the
+/// `UnsafeCell<T>` inside opts out of `Sync`, so a `&Cell<T>` can never be
+/// shared between threads.
+#[repr(transparent)]
+pub struct Cell<T: ?Sized> {
+    value: UnsafeCell<T>,
 }
 
 impl<T> Cell<T> {
-    pub fn new(value: T) -> Self {
-        todo!()
+    /// Creates a new `Cell` containing the given value.
+    pub const fn new(value: T) -> Self {
+        Cell {
+            value: UnsafeCell::new(value),
+        }
     }
 
     /// Returns a copy of the contained value.
@@ -21,49 +35,32 @@ impl<T> Cell<T> {
     {
         // SAFETY: This can cause data races if called from a separate thread,
         // but `Cell` is `!Sync` so this won't happen.
-
-        /*
-         * TODO
-         */
-        todo!()
+        unsafe { *self.value.get() }
     }
 
     /// Replaces the contained value with value, and returns the old contained value.
-    /// Hint: use mem::replace
     pub fn replace(&self, value: T) -> T {
         // SAFETY: This can cause data races if called from a separate thread,
         // but `Cell` is `!Sync` so this won't happen.
-        // Hint: use mem::replace
-
-        /*
-         * TODO
-         */
-        todo!()
+        mem::replace(unsafe { &mut *self.value.get() }, value)
     }
 
     /// Sets the contained value while dropping old value.
-    /// Hint: use self::replace and drop
     pub fn set(&self, value: T) {
-        /*
-         * TODO
-         */
+        let old = self.replace(value);
+        drop(old);
     }
 
     /// Takes the value of the cell, leaving Default::default() in its place.
-    /// Hint: use self::replace and Default::default()
     pub fn take(&self) -> T
     where
         T: Default,
     {
-        /*
-         * TODO
-         */
-        todo!()
+        self.replace(Default::default())
     }
 
     /// Swaps the values of two Cells. Difference with std::mem::swap is that this
     /// function doesn’t require &mut reference.
-    /// Hint: use ptr::swap
     pub fn swap(&self, other: &Cell<T>) {
         if ptr::eq(self, other) {
             return;
@@ -73,45 +70,508 @@ impl<T> Cell<T> {
         // is `!Sync` so this won't happen. This also won't invalidate any
         // pointers since `Cell` makes sure nothing else will be pointing into
         // either of these `Cell`s.
-
-        /*
-         * TODO
-         */
+        unsafe {
+            ptr::swap(self.value.get(), other.value.get());
+        }
     }
 
     /// Unwraps the value.
-    /// Hint: use UnsafeCell::into_inner
     pub fn into_inner(self) -> T {
-        /*
-         * TODO
-         */
-        todo!()
+        self.value.into_inner()
+    }
+
+    /// Updates the contained value using `f` and returns the new value.
+    ///
+    /// `f` works on a copy, so the cell is left untouched if it panics, and
+    /// a write made by `f` to this same cell is overwritten by the result.
+    pub fn update(&self, f: impl FnOnce(T) -> T) -> T
+    where
+        T: Copy,
+    {
+        let new = f(self.get());
+        self.set(new);
+        new
     }
 
-    /* More ... */
+    /// Stores the value returned by `f` if it is `Some`, leaving the cell
+    /// unchanged otherwise. Returns `Ok(previous)` if the value was updated,
+    /// `Err(previous)` if not.
+    pub fn fetch_update(&self, f: impl FnOnce(T) -> Option<T>) -> Result<T, T>
+    where
+        T: Copy,
+    {
+        let prev = self.get();
+        match f(prev) {
+            Some(next) => {
+                self.set(next);
+                Ok(prev)
+            }
+            None => Err(prev),
+        }
+    }
+
+    /// Replaces the contained value with the result of calling `f` on it.
+    ///
+    /// The value is taken out while `f` runs, so the cell holds
+    /// `Default::default()` if `f` panics or looks at this same cell.
+    pub fn map_in_place(&self, f: impl FnOnce(T) -> T)
+    where
+        T: Default,
+    {
+        let value = self.take();
+        self.set(f(value));
+    }
+
+    /// Calls `f` with a mutable reference to the contained value and returns its result.
+    ///
+    /// Like `map_in_place`, the value is taken out while `f` runs: reentrant
+    /// reads see `Default::default()`, and reentrant writes are overwritten.
+    pub fn with_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> R
+    where
+        T: Default,
+    {
+        let mut value = self.take();
+        let result = f(&mut value);
+        self.set(value);
+        result
+    }
 }
 
-impl<T> Cell<T> {
+impl<T: ?Sized> Cell<T> {
     /// Returns a raw pointer to the underlying data in this cell.
-    /// Hint:: use UnsafeCell::get
-    pub fn as_ptr(&self) -> *mut T {
-        /*
-         * TODO
-         */
-        todo!()
+    pub const fn as_ptr(&self) -> *mut T {
+        self.value.get()
     }
 
     /// Returns a mutable reference to the underlying data.
     /// This call borrows Cell mutably (at compile-time) which guarantees that we possess the only reference.
-    /// Hint: use UnsafeCell::get_mut
     pub fn get_mut(&mut self) -> &mut T {
-        /*
-         * TODO
-         */
-        todo!()
+        self.value.get_mut()
+    }
+
+    /// Returns a `&Cell<T>` from a `&mut T`.
+    pub fn from_mut(t: &mut T) -> &Cell<T> {
+        // SAFETY: `Cell<T>` is `repr(transparent)` over `UnsafeCell<T>`, which
+        // has the same memory layout as `T`, and `&mut` guarantees unique access.
+        unsafe { &*(t as *mut T as *const Cell<T>) }
+    }
+}
+
+impl<T> Cell<[T]> {
+    /// Returns a `&[Cell<T>]` from a `&Cell<[T]>`.
+    pub fn as_slice_of_cells(&self) -> &[Cell<T>] {
+        // SAFETY: `Cell<T>` has the same memory layout as `T`.
+        unsafe { &*(self as *const Cell<[T]> as *const [Cell<T>]) }
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for Cell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cell").field("value", &self.get()).finish()
+    }
+}
+
+impl<T: Copy> Clone for Cell<T> {
+    fn clone(&self) -> Cell<T> {
+        Cell::new(self.get())
+    }
+}
+
+impl<T: Default> Default for Cell<T> {
+    fn default() -> Cell<T> {
+        Cell::new(Default::default())
+    }
+}
+
+impl<T> From<T> for Cell<T> {
+    fn from(value: T) -> Cell<T> {
+        Cell::new(value)
+    }
+}
+
+impl<T: PartialEq + Copy> PartialEq for Cell<T> {
+    fn eq(&self, other: &Cell<T>) -> bool {
+        self.get() == other.get()
+    }
+}
+
+impl<T: Eq + Copy> Eq for Cell<T> {}
+
+impl<T: PartialOrd + Copy> PartialOrd for Cell<T> {
+    fn partial_cmp(&self, other: &Cell<T>) -> Option<Ordering> {
+        self.get().partial_cmp(&other.get())
+    }
+}
+
+impl<T: Ord + Copy> Ord for Cell<T> {
+    fn cmp(&self, other: &Cell<T>) -> Ordering {
+        self.get().cmp(&other.get())
+    }
+}
+
+/// A cell which can be written to only once.
+///
+/// Unlike `Cell<Option<T>>`, a `OnceCell` hands out `&T` into its value, since
+/// the value is never replaced once it has been set through a shared reference.
+pub struct OnceCell<T> {
+    inner: UnsafeCell<Option<T>>,
+    initializing: Cell<bool>,
+}
+
+impl<T> OnceCell<T> {
+    /// Creates a new empty cell.
+    pub const fn new() -> Self {
+        OnceCell {
+            inner: UnsafeCell::new(None),
+            initializing: Cell::new(false),
+        }
+    }
+
+    /// Gets the reference to the underlying value, or `None` if the cell is empty.
+    pub fn get(&self) -> Option<&T> {
+        // SAFETY: a `Some` is never overwritten through `&self`, so handing out
+        // shared references into it is fine.
+        unsafe { &*self.inner.get() }.as_ref()
+    }
+
+    /// Gets the mutable reference to the underlying value.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        self.inner.get_mut().as_mut()
+    }
+
+    /// Sets the contents of the cell to `value`, or returns it back if the
+    /// cell was already full.
+    /// panic if called from within the initializer of this cell.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        if self.initializing.get() {
+            panic!("reentrant init");
+        }
+        if self.get().is_some() {
+            return Err(value);
+        }
+
+        // SAFETY: the cell is empty, so no reference into it exists.
+        unsafe { *self.inner.get() = Some(value) };
+        Ok(())
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell was empty.
+    /// panic if `f` tries to initialize the cell again.
+    pub fn get_or_init<F>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> T,
+    {
+        match self.get_or_try_init(|| Ok::<T, Infallible>(f())) {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell was
+    /// empty. If `f` fails, the error is returned and the cell stays empty.
+    /// panic if `f` tries to initialize the cell again.
+    pub fn get_or_try_init<F, E>(&self, f: F) -> Result<&T, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        if let Some(value) = self.get() {
+            return Ok(value);
+        }
+        if self.initializing.replace(true) {
+            panic!("reentrant init");
+        }
+
+        let _reset = ResetOnDrop(&self.initializing);
+        let value = f()?;
+
+        // SAFETY: the cell is still empty, since both `set` and this method
+        // refuse to run while `initializing` is set.
+        unsafe { *self.inner.get() = Some(value) };
+        Ok(self.get().unwrap())
+    }
+
+    /// Takes the value out of this cell, moving it back to an uninitialized state.
+    pub fn take(&mut self) -> Option<T> {
+        self.inner.get_mut().take()
+    }
+
+    /// Consumes the cell, returning the wrapped value.
+    pub fn into_inner(self) -> Option<T> {
+        self.inner.into_inner()
     }
 }
 
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        OnceCell::new()
+    }
+}
+
+/// Clears the `initializing` flag even if the initializer panics.
+struct ResetOnDrop<'a>(&'a Cell<bool>);
+
+impl Drop for ResetOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
+/// A value which is initialized on the first access.
+pub struct LazyCell<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    init: Cell<Option<F>>,
+}
+
+impl<T, F: FnOnce() -> T> LazyCell<T, F> {
+    /// Creates a new lazy value with the given initializing function.
+    pub const fn new(f: F) -> Self {
+        LazyCell {
+            cell: OnceCell::new(),
+            init: Cell::new(Some(f)),
+        }
+    }
+
+    /// Forces the evaluation of this lazy value and returns a reference to the result.
+    /// panic if the initializer panicked before, or dereferences this value again.
+    pub fn force(this: &LazyCell<T, F>) -> &T {
+        this.cell.get_or_init(|| match this.init.take() {
+            Some(f) => f(),
+            None => panic!("LazyCell instance has previously been poisoned"),
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for LazyCell<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        LazyCell::force(self)
+    }
+}
+
+/// A thread-safe mutable memory location.
+///
+/// `AtomicCell<T>` has the same API as `Cell<T>` but is `Sync`, so it can be
+/// shared between threads. The one exception is `swap`, which exchanges the
+/// value with one passed in instead of with another cell: swapping two cells
+/// in one atomic step can't be done with native atomics.
+///
+/// The primitive integers and floats, `bool` and `char` are accessed with
+/// native atomic instructions. Anything else is guarded by a lock from a
+/// global striped lock table, even if it is 1, 2, 4 or 8 bytes wide: such a
+/// type may have padding bytes, which are uninitialized, and reading them as
+/// part of an integer is undefined behavior.
+///
+/// Telling the primitives apart takes a `TypeId`, so apart from `new` and
+/// `into_inner`, the methods need `T: 'static`.
+pub struct AtomicCell<T> {
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: every access to `value` goes through an atomic instruction or the
+// lock table, so `AtomicCell<T>` can be shared as long as `T` can be sent.
+unsafe impl<T: Send> Send for AtomicCell<T> {}
+unsafe impl<T: Send> Sync for AtomicCell<T> {}
+
+/// Runs `$native` with `$atomic` aliased to the atomic integer type that
+/// matches the layout of `$t`, or `$fallback` if there is none or `$t` may
+/// have padding.
+macro_rules! atomic {
+    ($t:ty, $atomic:ident => $native:expr, $fallback:expr) => {
+        if !is_primitive::<$t>() {
+            $fallback
+        } else if fits::<$t, AtomicU8>() {
+            type $atomic = AtomicU8;
+            $native
+        } else if fits::<$t, AtomicU16>() {
+            type $atomic = AtomicU16;
+            $native
+        } else if fits::<$t, AtomicU32>() {
+            type $atomic = AtomicU32;
+            $native
+        } else if fits::<$t, AtomicU64>() {
+            type $atomic = AtomicU64;
+            $native
+        } else {
+            $fallback
+        }
+    };
+}
+
+impl<T> AtomicCell<T> {
+    /// Creates a new `AtomicCell` containing the given value.
+    pub const fn new(value: T) -> Self {
+        AtomicCell {
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Unwraps the value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: 'static> AtomicCell<T> {
+    /// Returns `true` if operations on `AtomicCell<T>` use native atomics
+    /// instead of the lock table.
+    pub fn is_lock_free() -> bool {
+        atomic!(T, A => true, false)
+    }
+
+    /// Returns a copy of the contained value.
+    pub fn get(&self) -> T
+    where
+        T: Copy,
+    {
+        let src = self.value.get();
+        // SAFETY: `fits` guarantees `src` is a valid, aligned `A`; otherwise
+        // the stripe lock serializes us with every other access to `src`.
+        unsafe {
+            atomic!(
+                T,
+                A => from_bits((*(src as *const A)).load(atomic::Ordering::Acquire)),
+                {
+                    let _guard = lock(src as usize);
+                    ptr::read(src)
+                }
+            )
+        }
+    }
+
+    /// Replaces the contained value with value, and returns the old contained value.
+    pub fn replace(&self, value: T) -> T {
+        let dst = self.value.get();
+        // SAFETY: see `get`. The old value is moved out exactly once, and is
+        // dropped by the caller after the lock has been released.
+        unsafe {
+            atomic!(
+                T,
+                A => from_bits((*(dst as *const A)).swap(into_bits(value), atomic::Ordering::AcqRel)),
+                {
+                    let _guard = lock(dst as usize);
+                    ptr::replace(dst, value)
+                }
+            )
+        }
+    }
+
+    /// Sets the contained value while dropping old value.
+    pub fn set(&self, value: T) {
+        let old = self.replace(value);
+        drop(old);
+    }
+
+    /// Takes the value of the cell, leaving Default::default() in its place.
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(Default::default())
+    }
+
+    /// Stores `value` in the cell and returns the old value, in one atomic
+    /// step.
+    ///
+    /// Unlike `Cell::swap`, this doesn't exchange the values of two cells:
+    /// the native atomics only update one location at a time.
+    pub fn swap(&self, value: T) -> T {
+        self.replace(value)
+    }
+}
+
+/// Atomic integer types that a small `T` can be reinterpreted as.
+trait NativeAtomic {
+    type Bits;
+
+    fn load(&self, order: atomic::Ordering) -> Self::Bits;
+    fn swap(&self, bits: Self::Bits, order: atomic::Ordering) -> Self::Bits;
+}
+
+macro_rules! impl_native_atomic {
+    ($($atomic:ty => $bits:ty),*) => {
+        $(
+            impl NativeAtomic for $atomic {
+                type Bits = $bits;
+
+                fn load(&self, order: atomic::Ordering) -> $bits {
+                    <$atomic>::load(self, order)
+                }
+
+                fn swap(&self, bits: $bits, order: atomic::Ordering) -> $bits {
+                    <$atomic>::swap(self, bits, order)
+                }
+            }
+        )*
+    };
+}
+
+impl_native_atomic!(AtomicU8 => u8, AtomicU16 => u16, AtomicU32 => u32, AtomicU64 => u64);
+
+/// Returns `true` if `T` is a primitive that has no padding bytes, so every
+/// byte of it is initialized and may be read as part of an integer.
+///
+/// Layout alone can't tell: `#[repr(C, align(4))] struct P(u8, u16)` is as big
+/// and as aligned as a `u32`, but its second byte is padding. The `TypeId`s
+/// are known after monomorphization, so this folds into a constant.
+fn is_primitive<T: 'static>() -> bool {
+    macro_rules! is_one_of {
+        ($($p:ty),*) => {
+            $(TypeId::of::<T>() == TypeId::of::<$p>())||*
+        };
+    }
+
+    is_one_of!(bool, char, u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64)
+}
+
+/// Returns `true` if a `T` can be accessed in place as an `A`.
+const fn fits<T, A>() -> bool {
+    mem::size_of::<T>() == mem::size_of::<A>() && mem::align_of::<T>() >= mem::align_of::<A>()
+}
+
+/// Moves `value` into an integer of the same size.
+unsafe fn into_bits<T, B>(value: T) -> B {
+    mem::transmute_copy(&mem::ManuallyDrop::new(value))
+}
+
+/// Moves `bits` back into the `T` they were created from.
+unsafe fn from_bits<B, T>(bits: B) -> T {
+    mem::transmute_copy(&bits)
+}
+
+const LOCK_STRIPES: usize = 67;
+
+/// Spin locks for the cells that don't fit a native atomic, picked by address.
+static LOCKS: [AtomicBool; LOCK_STRIPES] = [const { AtomicBool::new(false) }; LOCK_STRIPES];
+
+/// Releases its stripe of the lock table when dropped.
+struct StripeGuard(&'static AtomicBool);
+
+impl Drop for StripeGuard {
+    fn drop(&mut self) {
+        self.0.store(false, atomic::Ordering::Release);
+    }
+}
+
+fn lock(addr: usize) -> StripeGuard {
+    let stripe = &LOCKS[addr % LOCK_STRIPES];
+    while stripe
+        .compare_exchange_weak(
+            false,
+            true,
+            atomic::Ordering::Acquire,
+            atomic::Ordering::Relaxed,
+        )
+        .is_err()
+    {
+        hint::spin_loop();
+    }
+    StripeGuard(stripe)
+}
+
+// `cell_is_not_sync` is parked behind a `skip` feature that is deliberately
+// never declared, since it doesn't compile.
+#[allow(unexpected_cfgs)]
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -198,6 +658,28 @@ mod tests {
         assert_eq!(c.get(), 6);
     }
 
+    #[test]
+    fn from_mut() {
+        let mut x = 5;
+        let c = Cell::from_mut(&mut x);
+        c.set(c.get() + 1);
+
+        assert_eq!(x, 6);
+    }
+
+    #[test]
+    fn as_slice_of_cells() {
+        let slice: &mut [i32] = &mut [1, 2, 3];
+        let cells: &[Cell<i32>] = Cell::from_mut(slice).as_slice_of_cells();
+
+        // in-place reversal through shared references only
+        let (first, last) = (&cells[0], &cells[cells.len() - 1]);
+        first.swap(last);
+
+        assert_eq!(cells.len(), 3);
+        assert_eq!(slice, &[3, 2, 1]);
+    }
+
     #[test]
     fn cell_is_send() {
         let cell = Cell::new(5);
@@ -210,6 +692,213 @@ mod tests {
         t1.join().unwrap();
     }
 
+    #[test]
+    fn atomic_cell_basics() {
+        let c = AtomicCell::new(5_u32);
+        assert_eq!(c.replace(6), 5);
+        c.set(7);
+        assert_eq!(c.take(), 7);
+        assert_eq!(c.get(), 0);
+
+        assert_eq!(c.swap(42), 0);
+        assert_eq!(c.into_inner(), 42);
+    }
+
+    #[test]
+    fn atomic_cell_swap_keeps_every_value() {
+        use std::sync::Barrier;
+
+        const THREADS: usize = 4;
+        let shared = Arc::new(AtomicCell::new(0_usize));
+        let barrier = Arc::new(Barrier::new(THREADS));
+
+        // Each thread starts with its own token and keeps trading it for the
+        // one in the cell; in the end no token may be lost or duplicated.
+        let handles: Vec<_> = (1..=THREADS)
+            .map(|token| {
+                let (shared, barrier) = (Arc::clone(&shared), Arc::clone(&barrier));
+                thread::spawn(move || {
+                    let mut held = token;
+                    barrier.wait();
+                    for _ in 0..10_000 {
+                        held = shared.swap(held);
+                    }
+                    held
+                })
+            })
+            .collect();
+        let mut tokens: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        tokens.push(shared.get());
+        tokens.sort();
+
+        assert_eq!(tokens, (0..=THREADS).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn atomic_cell_is_lock_free_for_native_sizes() {
+        assert!(AtomicCell::<bool>::is_lock_free());
+        assert!(AtomicCell::<u16>::is_lock_free());
+        assert!(AtomicCell::<char>::is_lock_free());
+        assert!(AtomicCell::<usize>::is_lock_free());
+        assert!(!AtomicCell::<[u8; 4]>::is_lock_free()); // under-aligned
+        assert!(!AtomicCell::<[u64; 4]>::is_lock_free());
+
+        // as big and aligned as a `u32`, but with a padding byte
+        #[repr(C, align(4))]
+        struct Padded(u8, u16);
+        assert!(!AtomicCell::<Padded>::is_lock_free());
+        assert!(!AtomicCell::<Option<u8>>::is_lock_free());
+    }
+
+    #[test]
+    fn atomic_cell_is_sync() {
+        let flag = Arc::new(AtomicCell::new(false));
+        let wide = Arc::new(AtomicCell::new([0_u64; 4]));
+
+        let handles: Vec<_> = (1..=4_u64)
+            .map(|i| {
+                let (flag, wide) = (Arc::clone(&flag), Arc::clone(&wide));
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        // a torn read would mix the lanes of two writers
+                        let old = wide.replace([i; 4]);
+                        assert!(old.iter().all(|&lane| lane == old[0]));
+                    }
+                    flag.set(true);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert!(flag.get());
+        assert!(wide.get().iter().all(|&lane| lane == wide.get()[0]));
+    }
+
+    #[test]
+    fn atomic_cell_drops_values() {
+        let counter = std::rc::Rc::new(());
+        let c = AtomicCell::new(std::rc::Rc::clone(&counter));
+        c.set(std::rc::Rc::clone(&counter));
+        assert_eq!(std::rc::Rc::strong_count(&counter), 2);
+
+        drop(c);
+        assert_eq!(std::rc::Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn update() {
+        let c = Cell::new(5);
+        assert_eq!(c.update(|x| x + 1), 6);
+        assert_eq!(c.get(), 6);
+
+        assert_eq!(c.fetch_update(|x| (x < 10).then_some(x * 2)), Ok(6));
+        assert_eq!(c.fetch_update(|x| (x < 10).then_some(x * 2)), Err(12));
+        assert_eq!(c.get(), 12);
+    }
+
+    #[test]
+    fn update_reentrant() {
+        let c = Cell::new(1);
+        assert_eq!(c.update(|x| c.update(|y| y * 10) + x), 11);
+        assert_eq!(c.get(), 11);
+    }
+
+    #[test]
+    fn with_mut() {
+        let c = Cell::new(vec![1, 2]);
+        c.map_in_place(|mut v| {
+            v.push(3);
+            v
+        });
+        let len = c.with_mut(|v| {
+            // the value is taken out while the closure runs
+            assert!(c.take().is_empty());
+            v.push(4);
+            v.len()
+        });
+
+        assert_eq!(len, 4);
+        assert_eq!(c.into_inner(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn with_mut_panic_leaves_default() {
+        let c = Cell::new(String::from("hello"));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            c.with_mut(|s| {
+                s.push('!');
+                panic!("boom");
+            })
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(c.take(), "");
+    }
+
+    #[test]
+    fn std_traits() {
+        #[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+        struct Counter {
+            hits: Cell<u32>,
+        }
+
+        let a = Counter::default();
+        let b = a.clone();
+        a.hits.set(2);
+
+        assert_eq!(format!("{:?}", a), "Counter { hits: Cell { value: 2 } }");
+        assert_eq!(b.hits, Cell::from(0));
+        assert!(b < a);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn once_cell() {
+        let cell = OnceCell::new();
+        assert!(cell.get().is_none());
+
+        assert_eq!(cell.get_or_init(|| 42), &42);
+        assert_eq!(cell.get_or_init(|| unreachable!()), &42);
+        assert_eq!(cell.set(7), Err(7));
+
+        let mut cell = cell;
+        assert_eq!(cell.take(), Some(42));
+        assert_eq!(cell.set(7), Ok(()));
+        assert_eq!(cell.into_inner(), Some(7));
+    }
+
+    #[test]
+    fn once_cell_get_or_try_init() {
+        let cell: OnceCell<i32> = OnceCell::new();
+
+        assert_eq!(cell.get_or_try_init(|| Err("not yet")), Err("not yet"));
+        assert!(cell.get().is_none());
+        assert_eq!(cell.get_or_try_init(|| Ok::<_, ()>(42)), Ok(&42));
+    }
+
+    #[test]
+    #[should_panic(expected = "reentrant init")]
+    fn once_cell_reentrant_init() {
+        let cell = OnceCell::new();
+        cell.get_or_init(|| *cell.get_or_init(|| 1) + 1);
+    }
+
+    #[test]
+    fn lazy_cell() {
+        let calls = Cell::new(0);
+        let lazy = LazyCell::new(|| {
+            calls.set(calls.get() + 1);
+            String::from("hello")
+        });
+        assert_eq!(calls.get(), 0);
+
+        assert_eq!(lazy.len(), 5);
+        assert_eq!(&*lazy, "hello");
+        assert_eq!(calls.get(), 1);
+    }
+
     #[cfg(feature = "skip")]
     #[test]
     fn cell_is_not_sync() {
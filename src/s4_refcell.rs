@@ -1,20 +1,117 @@
+#[cfg(feature = "borrow-timeline")]
+use self::timeline::{Action, BorrowKind};
 use crate::s3_cell::Cell;
+#[cfg(feature = "debug-borrows")]
+use std::panic::Location;
 use std::{
     cell::UnsafeCell,
+    error::Error,
+    fmt, hint,
     marker::PhantomData,
+    mem::{self, ManuallyDrop},
     ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        LockResult, PoisonError, TryLockError, TryLockResult,
+    },
+    thread,
 };
 
+/// The borrow state of a `RefCell` or a `RwCell`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BorrowState {
+    Unused,
+    Shared(usize),
+    Exclusive,
+}
+
+impl fmt::Display for BorrowState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BorrowState::Unused => write!(f, "no borrows are active"),
+            BorrowState::Shared(1) => write!(f, "1 shared borrow is active"),
+            BorrowState::Shared(n) => write!(f, "{} shared borrows are active", n),
+            BorrowState::Exclusive => write!(f, "an exclusive borrow is active"),
+        }
+    }
+}
+
+/// What a `RefCell` does when a borrow conflicts with an active one.
+///
+/// A conflict is a metric rather than a crash only at `borrow_checked` and
+/// `borrow_mut_checked` call sites, under `Hook`, in release builds. Every
+/// other method that can conflict calls the hook too, but `borrow`,
+/// `borrow_mut`, `replace`, `replace_with`, `swap`, `take` and
+/// `borrow_many_mut` then panic under either policy, since they have no error
+/// to return. `try_borrow_many_mut` calls the hook and returns the error.
+///
+/// To always get the error back, call `try_borrow` or `try_borrow_mut`,
+/// which ignore the policy.
+#[derive(Debug, Clone, Copy)]
+pub enum ConflictPolicy {
+    /// Panic. This is the policy of `RefCell::new`.
+    Panic,
+    /// Pass the error to the hook, e.g. to count it as a metric. Then
+    /// `borrow_checked` and `borrow_mut_checked` panic in debug builds and
+    /// return the error in release builds.
+    Hook(fn(&(dyn Error + 'static))),
+}
+
 /// A mutable memory location with dynamically checked borrow rules
-#[derive(Debug)]
 pub struct RefCell<T> {
-    _phantom: PhantomData<T>,
+    inner: UnsafeCell<T>,
+    state: Cell<BorrowState>,
+    policy: ConflictPolicy,
+    // The number of live `RefMut`s while `state` is `Exclusive`, which is
+    // more than one only after `RefMut::map_split`.
+    mut_guards: Cell<usize>,
+    #[cfg(feature = "debug-borrows")]
+    borrowed_at: BorrowSites,
 }
 
 impl<T> RefCell<T> {
     pub fn new(value: T) -> RefCell<T> {
+        RefCell::with_policy(value, ConflictPolicy::Panic)
+    }
+
+    /// Creates a `RefCell` that handles conflicting borrows according to `policy`.
+    pub fn with_policy(value: T, policy: ConflictPolicy) -> RefCell<T> {
         RefCell {
-            _phantom: PhantomData,
+            inner: UnsafeCell::new(value),
+            state: Cell::new(BorrowState::Unused),
+            policy,
+            mut_guards: Cell::new(0),
+            #[cfg(feature = "debug-borrows")]
+            borrowed_at: BorrowSites::default(),
+        }
+    }
+
+    /// The policy for conflicting borrows of this cell.
+    pub fn policy(&self) -> ConflictPolicy {
+        self.policy
+    }
+
+    /// Applies the conflict policy to `err`, and returns it if the caller
+    /// should see it instead of a panic.
+    #[track_caller]
+    fn on_conflict<E: Error + 'static>(&self, err: E) -> E {
+        self.call_hook(&err);
+        match self.policy {
+            ConflictPolicy::Panic => panic!("{}", err),
+            ConflictPolicy::Hook(_) => {
+                if cfg!(debug_assertions) {
+                    panic!("{}", err);
+                }
+                err
+            }
+        }
+    }
+
+    /// Passes `err` to the hook of a `ConflictPolicy::Hook`, if any.
+    fn call_hook(&self, err: &(dyn Error + 'static)) {
+        if let ConflictPolicy::Hook(hook) = self.policy {
+            hook(err);
         }
     }
 }
@@ -24,12 +121,60 @@ impl<T> RefCell<T> {
     ///
     /// The borrow lasts until the returned `Ref` exits scope. Multiple
     /// immutable borrows can be taken out at the same time.
-    /// panic if already mutably borrowed
-    pub fn borrow(&self) -> Option<&T> {
-        /*
-         * TODO
-         */
-        todo!()
+    /// panic if already mutably borrowed, after calling the hook of a
+    /// `ConflictPolicy::Hook`. This panics under every policy; use
+    /// `borrow_checked` to follow it.
+    #[track_caller]
+    pub fn borrow(&self) -> Ref<'_, T> {
+        match self.try_borrow() {
+            Ok(r) => r,
+            Err(err) => panic!("{}", self.on_conflict(err)),
+        }
+    }
+
+    /// Immutably borrows the wrapped value, handling a conflicting mutable
+    /// borrow according to the cell's `ConflictPolicy`.
+    #[track_caller]
+    pub fn borrow_checked(&self) -> Result<Ref<'_, T>, BorrowError> {
+        self.try_borrow().map_err(|err| self.on_conflict(err))
+    }
+
+    /// Immutably borrows the wrapped value, returning an error if the value is
+    /// currently mutably borrowed.
+    #[track_caller]
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
+        match self.state.get() {
+            BorrowState::Unused => self.state.set(BorrowState::Shared(1)),
+            BorrowState::Shared(n) => self.state.set(BorrowState::Shared(n + 1)),
+            state @ BorrowState::Exclusive => {
+                #[cfg(feature = "borrow-timeline")]
+                timeline::record(&self.state, BorrowKind::Shared, Action::Conflict, None);
+
+                return Err(BorrowError {
+                    state,
+                    #[cfg(feature = "debug-borrows")]
+                    held_at: self.borrowed_at.oldest(),
+                });
+            }
+        }
+        #[cfg(feature = "debug-borrows")]
+        self.borrowed_at.push(Location::caller());
+
+        Ok(Ref {
+            value: NonNull::from(&self.inner).cast(),
+            upgradable: true,
+            marker: PhantomData,
+            borrow: BorrowRef {
+                state: &self.state,
+                guards: &self.mut_guards,
+                #[cfg(feature = "debug-borrows")]
+                sites: &self.borrowed_at,
+                #[cfg(feature = "debug-borrows")]
+                location: Location::caller(),
+                #[cfg(feature = "borrow-timeline")]
+                event: timeline::record(&self.state, BorrowKind::Shared, Action::Acquire, None),
+            },
+        })
     }
 
     /// Mutably borrows the wrapped value.
@@ -38,11 +183,1195 @@ impl<T> RefCell<T> {
     /// The borrow lasts until the returned `RefMut` or all `RefMut`s derived
     /// from it exit scope. The value cannot be borrowed while this borrow is
     /// active.
-    pub fn borrow_mut(&self) -> Option<&mut T> {
-        /*
-         * TODO
-         */
-        todo!()
+    /// Like `borrow`, the hook of a `ConflictPolicy::Hook` is called before
+    /// panicking, and this panics under every policy; use
+    /// `borrow_mut_checked` to follow it.
+    #[track_caller]
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        match self.try_borrow_mut() {
+            Ok(r) => r,
+            Err(err) => panic!("{}", self.on_conflict(err)),
+        }
+    }
+
+    /// Mutably borrows the wrapped value, handling a conflicting borrow
+    /// according to the cell's `ConflictPolicy`.
+    #[track_caller]
+    pub fn borrow_mut_checked(&self) -> Result<RefMut<'_, T>, BorrowMutError> {
+        self.try_borrow_mut().map_err(|err| self.on_conflict(err))
+    }
+
+    /// Mutably borrows the wrapped value, returning an error if the value is
+    /// currently borrowed.
+    #[track_caller]
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, BorrowMutError> {
+        match self.state.get() {
+            BorrowState::Unused => {
+                self.state.set(BorrowState::Exclusive);
+                self.mut_guards.set(1);
+            }
+            state => {
+                #[cfg(feature = "borrow-timeline")]
+                timeline::record(&self.state, BorrowKind::Exclusive, Action::Conflict, None);
+
+                return Err(BorrowMutError {
+                    state,
+                    #[cfg(feature = "debug-borrows")]
+                    held_at: self.borrowed_at.oldest(),
+                });
+            }
+        }
+        #[cfg(feature = "debug-borrows")]
+        self.borrowed_at.push(Location::caller());
+
+        Ok(RefMut {
+            value: NonNull::from(&self.inner).cast(),
+            borrow: BorrowRefMut {
+                state: &self.state,
+                guards: &self.mut_guards,
+                #[cfg(feature = "debug-borrows")]
+                sites: &self.borrowed_at,
+                #[cfg(feature = "debug-borrows")]
+                location: Location::caller(),
+                #[cfg(feature = "borrow-timeline")]
+                event: timeline::record(&self.state, BorrowKind::Exclusive, Action::Acquire, None),
+            },
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<T> RefCell<T> {
+    /// Replaces the wrapped value with a new one, returning the old value.
+    /// panic if the value is currently borrowed.
+    #[track_caller]
+    pub fn replace(&self, t: T) -> T {
+        mem::replace(&mut *self.borrow_mut(), t)
+    }
+
+    /// Replaces the wrapped value with a new one computed from `f`, returning
+    /// the old value.
+    /// panic if the value is currently borrowed.
+    #[track_caller]
+    pub fn replace_with<F>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut T) -> T,
+    {
+        let mut guard = self.borrow_mut();
+        let new = f(&mut guard);
+        mem::replace(&mut *guard, new)
+    }
+
+    /// Swaps the wrapped value of `self` with the wrapped value of `other`.
+    /// panic if either value is currently borrowed, or if `self` and `other`
+    /// are the same cell.
+    #[track_caller]
+    pub fn swap(&self, other: &RefCell<T>) {
+        mem::swap(&mut *self.borrow_mut(), &mut *other.borrow_mut())
+    }
+
+    /// Takes the wrapped value, leaving `Default::default()` in its place.
+    /// panic if the value is currently borrowed.
+    #[track_caller]
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(Default::default())
+    }
+
+    /// Consumes the `RefCell`, returning the wrapped value.
+    pub fn into_inner(self) -> T {
+        // Taking `self` by value guarantees that no guard is alive.
+        self.inner.into_inner()
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    /// This call borrows `RefCell` mutably (at compile-time) so there is no
+    /// need for dynamic checks.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    /// Undoes the effect of leaked guards on the borrow state, e.g. after
+    /// `mem::forget` was called on a `Ref` or `RefMut`.
+    ///
+    /// This call borrows `RefCell` mutably, so no guard can still be in use.
+    pub fn undo_leak(&mut self) -> &mut T {
+        *self.state.get_mut() = BorrowState::Unused;
+        *self.mut_guards.get_mut() = 0;
+        #[cfg(feature = "debug-borrows")]
+        self.borrowed_at.0.get_mut().clear();
+
+        self.get_mut()
+    }
+}
+
+impl<T> RefCell<T> {
+    /// Mutably borrows all of the given cells at once.
+    /// panic if any of them is already borrowed, after calling its hook, or
+    /// if the same cell is passed twice. No borrow is kept when this panics.
+    #[track_caller]
+    pub fn borrow_many_mut<const N: usize>(cells: [&RefCell<T>; N]) -> [RefMut<'_, T>; N] {
+        match RefCell::try_borrow_many_mut(cells) {
+            Ok(r) => r,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Mutably borrows all of the given cells at once, or none of them.
+    ///
+    /// Returns an error naming the first cell that is already borrowed, or
+    /// the first cell that was passed twice. In either case, the borrows that
+    /// were already taken are released before returning. A borrowed cell
+    /// with a `ConflictPolicy::Hook` passes its conflict to the hook first.
+    #[track_caller]
+    pub fn try_borrow_many_mut<const N: usize>(
+        cells: [&RefCell<T>; N],
+    ) -> Result<[RefMut<'_, T>; N], BorrowManyError> {
+        for (second, cell) in cells.iter().enumerate() {
+            if let Some(first) = cells[..second].iter().position(|c| ptr::eq(*c, *cell)) {
+                return Err(BorrowManyError::Duplicate { first, second });
+            }
+        }
+
+        let mut guards = Vec::with_capacity(N);
+        for (index, cell) in cells.iter().enumerate() {
+            match cell.try_borrow_mut() {
+                Ok(guard) => guards.push(guard),
+                // dropping `guards` releases the cells borrowed so far
+                Err(source) => {
+                    cell.call_hook(&source);
+                    return Err(BorrowManyError::Busy { index, source });
+                }
+            }
+        }
+        match guards.try_into() {
+            Ok(guards) => Ok(guards),
+            Err(_) => unreachable!(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RefCell<T> {
+    /// Never panics: a mutably borrowed value is shown as `<borrowed>`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct BorrowedPlaceholder;
+
+        impl fmt::Debug for BorrowedPlaceholder {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("<borrowed>")
+            }
+        }
+
+        let state = self.state.get();
+        let mut d = f.debug_struct("RefCell");
+        match self.try_borrow() {
+            Ok(value) => d.field("value", &value),
+            Err(_) => d.field("value", &BorrowedPlaceholder),
+        };
+        d.field("state", &state).finish()
+    }
+}
+
+/// An error returned by `RefCell::try_borrow`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BorrowError {
+    state: BorrowState,
+    #[cfg(feature = "debug-borrows")]
+    held_at: Option<&'static Location<'static>>,
+}
+
+impl BorrowError {
+    /// The borrow state that prevented the borrow.
+    pub fn state(&self) -> BorrowState {
+        self.state
+    }
+
+    /// Where the conflicting borrow was taken.
+    #[cfg(feature = "debug-borrows")]
+    pub fn held_at(&self) -> Option<&'static Location<'static>> {
+        self.held_at
+    }
+}
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "already mutably borrowed: {}", self.state)?;
+        #[cfg(feature = "debug-borrows")]
+        if let Some(location) = self.held_at {
+            write!(f, " (borrowed at {})", location)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for BorrowError {}
+
+/// An error returned by `RefCell::try_borrow_mut`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BorrowMutError {
+    state: BorrowState,
+    #[cfg(feature = "debug-borrows")]
+    held_at: Option<&'static Location<'static>>,
+}
+
+impl BorrowMutError {
+    /// The borrow state that prevented the borrow.
+    pub fn state(&self) -> BorrowState {
+        self.state
+    }
+
+    /// Where the oldest of the conflicting borrows was taken.
+    #[cfg(feature = "debug-borrows")]
+    pub fn held_at(&self) -> Option<&'static Location<'static>> {
+        self.held_at
+    }
+}
+
+impl fmt::Display for BorrowMutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "already borrowed: {}", self.state)?;
+        #[cfg(feature = "debug-borrows")]
+        if let Some(location) = self.held_at {
+            write!(f, " (borrowed at {})", location)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for BorrowMutError {}
+
+/// An error returned by `RefCell::try_borrow_many_mut`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BorrowManyError {
+    /// The cell at `index` is already borrowed.
+    Busy {
+        index: usize,
+        source: BorrowMutError,
+    },
+    /// The same cell was passed at both `first` and `second`.
+    Duplicate { first: usize, second: usize },
+}
+
+impl fmt::Display for BorrowManyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BorrowManyError::Busy { index, source } => write!(f, "cell #{}: {}", index, source),
+            BorrowManyError::Duplicate { first, second } => {
+                write!(f, "cells #{} and #{} are the same cell", first, second)
+            }
+        }
+    }
+}
+
+impl Error for BorrowManyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BorrowManyError::Busy { source, .. } => Some(source),
+            BorrowManyError::Duplicate { .. } => None,
+        }
+    }
+}
+
+/// Where each active borrow of a `RefCell` was taken, oldest first.
+#[cfg(feature = "debug-borrows")]
+#[derive(Default)]
+struct BorrowSites(Cell<Vec<&'static Location<'static>>>);
+
+#[cfg(feature = "debug-borrows")]
+impl BorrowSites {
+    fn push(&self, location: &'static Location<'static>) {
+        self.0.with_mut(|sites| sites.push(location));
+    }
+
+    fn remove(&self, location: &'static Location<'static>) {
+        self.0.with_mut(|sites| {
+            if let Some(i) = sites.iter().position(|&site| site == location) {
+                sites.remove(i);
+            }
+        });
+    }
+
+    fn oldest(&self) -> Option<&'static Location<'static>> {
+        self.0.with_mut(|sites| sites.first().copied())
+    }
+}
+
+#[cfg(feature = "debug-borrows")]
+impl fmt::Debug for BorrowSites {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0
+            .with_mut(|sites| f.debug_list().entries(sites.iter()).finish())
+    }
+}
+
+/// Releases one shared borrow of a `RefCell` when dropped.
+struct BorrowRef<'b> {
+    state: &'b Cell<BorrowState>,
+    // Only used to turn the borrow into a `BorrowRefMut`.
+    guards: &'b Cell<usize>,
+    #[cfg(feature = "debug-borrows")]
+    sites: &'b BorrowSites,
+    #[cfg(feature = "debug-borrows")]
+    location: &'static Location<'static>,
+    // When the borrow was acquired, if the timeline was recording.
+    #[cfg(feature = "borrow-timeline")]
+    event: Option<usize>,
+}
+
+impl Clone for BorrowRef<'_> {
+    fn clone(&self) -> Self {
+        match self.state.get() {
+            BorrowState::Shared(n) => self.state.set(BorrowState::Shared(n + 1)),
+            _ => unreachable!(),
+        }
+        #[cfg(feature = "debug-borrows")]
+        self.sites.push(self.location);
+
+        BorrowRef {
+            state: self.state,
+            guards: self.guards,
+            #[cfg(feature = "debug-borrows")]
+            sites: self.sites,
+            #[cfg(feature = "debug-borrows")]
+            location: self.location,
+            #[cfg(feature = "borrow-timeline")]
+            event: timeline::record_at(
+                self.state,
+                BorrowKind::Shared,
+                Action::Acquire,
+                None,
+                self.location,
+            ),
+        }
+    }
+}
+
+impl<'b> BorrowRef<'b> {
+    /// Turns the only shared borrow into an exclusive one without releasing
+    /// it in between, or gives it back if there are other shared borrows.
+    fn upgrade(self) -> Result<BorrowRefMut<'b>, BorrowRef<'b>> {
+        if self.state.get() != BorrowState::Shared(1) {
+            return Err(self);
+        }
+
+        let this = ManuallyDrop::new(self);
+        this.state.set(BorrowState::Exclusive);
+        this.guards.set(1);
+        #[cfg(feature = "borrow-timeline")]
+        if let Some(event) = this.event {
+            timeline::record_at(
+                this.state,
+                BorrowKind::Shared,
+                Action::Release,
+                Some(event),
+                this.location,
+            );
+        }
+
+        Ok(BorrowRefMut {
+            state: this.state,
+            guards: this.guards,
+            #[cfg(feature = "debug-borrows")]
+            sites: this.sites,
+            #[cfg(feature = "debug-borrows")]
+            location: this.location,
+            #[cfg(feature = "borrow-timeline")]
+            event: timeline::record_at(
+                this.state,
+                BorrowKind::Exclusive,
+                Action::Acquire,
+                None,
+                this.location,
+            ),
+        })
+    }
+}
+
+impl Drop for BorrowRef<'_> {
+    fn drop(&mut self) {
+        #[cfg(feature = "debug-borrows")]
+        self.sites.remove(self.location);
+        #[cfg(feature = "borrow-timeline")]
+        if let Some(event) = self.event {
+            timeline::record_at(
+                self.state,
+                BorrowKind::Shared,
+                Action::Release,
+                Some(event),
+                self.location,
+            );
+        }
+
+        match self.state.get() {
+            BorrowState::Shared(1) => self.state.set(BorrowState::Unused),
+            BorrowState::Shared(n) => self.state.set(BorrowState::Shared(n - 1)),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Releases the exclusive borrow of a `RefCell` once the last `RefMut` split
+/// off from it is dropped.
+struct BorrowRefMut<'b> {
+    state: &'b Cell<BorrowState>,
+    guards: &'b Cell<usize>,
+    #[cfg(feature = "debug-borrows")]
+    sites: &'b BorrowSites,
+    #[cfg(feature = "debug-borrows")]
+    location: &'static Location<'static>,
+    #[cfg(feature = "borrow-timeline")]
+    event: Option<usize>,
+}
+
+impl<'b> BorrowRefMut<'b> {
+    /// Turns the exclusive borrow into a shared one without releasing it in
+    /// between.
+    /// panic if the borrow was split by `RefMut::map_split`.
+    fn downgrade(self) -> BorrowRef<'b> {
+        assert_eq!(self.guards.get(), 1, "cannot downgrade a split RefMut");
+
+        let this = ManuallyDrop::new(self);
+        this.state.set(BorrowState::Shared(1));
+        this.guards.set(0);
+        #[cfg(feature = "borrow-timeline")]
+        if let Some(event) = this.event {
+            timeline::record_at(
+                this.state,
+                BorrowKind::Exclusive,
+                Action::Release,
+                Some(event),
+                this.location,
+            );
+        }
+
+        BorrowRef {
+            state: this.state,
+            guards: this.guards,
+            #[cfg(feature = "debug-borrows")]
+            sites: this.sites,
+            #[cfg(feature = "debug-borrows")]
+            location: this.location,
+            #[cfg(feature = "borrow-timeline")]
+            event: timeline::record_at(
+                this.state,
+                BorrowKind::Shared,
+                Action::Acquire,
+                None,
+                this.location,
+            ),
+        }
+    }
+
+    /// Creates another guard for a disjoint part of the same exclusive borrow.
+    fn split(&self) -> Self {
+        debug_assert_eq!(self.state.get(), BorrowState::Exclusive);
+        self.guards.set(self.guards.get() + 1);
+        #[cfg(feature = "debug-borrows")]
+        self.sites.push(self.location);
+
+        BorrowRefMut {
+            state: self.state,
+            guards: self.guards,
+            #[cfg(feature = "debug-borrows")]
+            sites: self.sites,
+            #[cfg(feature = "debug-borrows")]
+            location: self.location,
+            #[cfg(feature = "borrow-timeline")]
+            event: timeline::record_at(
+                self.state,
+                BorrowKind::Exclusive,
+                Action::Acquire,
+                None,
+                self.location,
+            ),
+        }
+    }
+}
+
+impl Drop for BorrowRefMut<'_> {
+    fn drop(&mut self) {
+        #[cfg(feature = "debug-borrows")]
+        self.sites.remove(self.location);
+        #[cfg(feature = "borrow-timeline")]
+        if let Some(event) = self.event {
+            timeline::record_at(
+                self.state,
+                BorrowKind::Exclusive,
+                Action::Release,
+                Some(event),
+                self.location,
+            );
+        }
+
+        match (self.state.get(), self.guards.get()) {
+            (BorrowState::Exclusive, 1) => self.state.set(BorrowState::Unused),
+            (BorrowState::Exclusive, n) => self.guards.set(n - 1),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// A wrapper type for an immutably borrowed value from a `RefCell<T>`.
+pub struct Ref<'b, T: ?Sized> {
+    value: NonNull<T>,
+    borrow: BorrowRef<'b>,
+    // Whether `value` may be written through once the borrow is exclusive.
+    // `Ref::map` can point to data the cell doesn't own, like a `&'static str`.
+    upgradable: bool,
+    // Invariant over `T`, unlike `&'b T`, since `Ref::try_upgrade` can turn
+    // any `Ref` that has not been mapped back into a `RefMut`.
+    marker: PhantomData<&'b mut T>,
+}
+
+impl<T: ?Sized> Deref for Ref<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: a `Ref` only exists while the state is `Shared`, so no
+        // exclusive reference to the value exists.
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<'b, T: ?Sized> Ref<'b, T> {
+    /// Copies a `Ref`, taking out another shared borrow.
+    ///
+    /// This is an associated function that needs to be used as `Ref::clone(...)`,
+    /// so that it doesn't shadow a `clone` method of the borrowed value.
+    #[allow(clippy::should_implement_trait)]
+    pub fn clone(orig: &Ref<'b, T>) -> Ref<'b, T> {
+        Ref {
+            value: orig.value,
+            borrow: orig.borrow.clone(),
+            upgradable: orig.upgradable,
+            marker: PhantomData,
+        }
+    }
+
+    /// Makes a new `Ref` for a component of the borrowed data.
+    pub fn map<U: ?Sized, F>(orig: Ref<'b, T>, f: F) -> Ref<'b, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        Ref {
+            value: NonNull::from(f(&*orig)),
+            borrow: orig.borrow,
+            upgradable: false,
+            marker: PhantomData,
+        }
+    }
+
+    /// Makes a new `Ref` for an optional component of the borrowed data, or
+    /// returns the original guard if `f` returns `None`.
+    pub fn filter_map<U: ?Sized, F>(orig: Ref<'b, T>, f: F) -> Result<Ref<'b, U>, Ref<'b, T>>
+    where
+        F: FnOnce(&T) -> Option<&U>,
+    {
+        match f(&*orig) {
+            Some(value) => Ok(Ref {
+                value: NonNull::from(value),
+                borrow: orig.borrow,
+                upgradable: false,
+                marker: PhantomData,
+            }),
+            None => Err(orig),
+        }
+    }
+
+    /// Splits a `Ref` into multiple `Ref`s for different components of the
+    /// borrowed data.
+    pub fn map_split<U: ?Sized, V: ?Sized, F>(orig: Ref<'b, T>, f: F) -> (Ref<'b, U>, Ref<'b, V>)
+    where
+        F: FnOnce(&T) -> (&U, &V),
+    {
+        let (a, b) = f(&*orig);
+        let borrow = orig.borrow.clone();
+        (
+            Ref {
+                value: NonNull::from(a),
+                borrow,
+                upgradable: false,
+                marker: PhantomData,
+            },
+            Ref {
+                value: NonNull::from(b),
+                borrow: orig.borrow,
+                upgradable: false,
+                marker: PhantomData,
+            },
+        )
+    }
+}
+
+impl<'b, T: ?Sized> Ref<'b, T> {
+    /// Turns a `Ref` into a `RefMut` without letting any other borrow in
+    /// between, or gives it back if there are other shared borrows.
+    ///
+    /// A `Ref` made by `Ref::map`, `Ref::filter_map` or `Ref::map_split`
+    /// can't be upgraded, since it may point to data the cell doesn't own.
+    ///
+    /// Since the result can be written through, a `Ref` can't be coerced to
+    /// a shorter lifetime first, which would let a short-lived value into
+    /// the cell:
+    ///
+    /// ```compile_fail,E0597
+    /// use smart_pointers::s4_refcell::{Ref, RefCell};
+    ///
+    /// let cell: RefCell<&'static str> = RefCell::new("static");
+    /// let local = String::from("local");
+    /// let r: Ref<'_, &str> = cell.borrow();
+    /// *Ref::try_upgrade(r).unwrap() = local.as_str();
+    /// drop(local);
+    /// println!("{}", *cell.borrow());
+    /// ```
+    pub fn try_upgrade(orig: Ref<'b, T>) -> Result<RefMut<'b, T>, Ref<'b, T>> {
+        if !orig.upgradable {
+            return Err(orig);
+        }
+
+        let Ref { value, borrow, .. } = orig;
+        match borrow.upgrade() {
+            Ok(borrow) => Ok(RefMut {
+                value,
+                borrow,
+                marker: PhantomData,
+            }),
+            Err(borrow) => Err(Ref {
+                value,
+                borrow,
+                upgradable: true,
+                marker: PhantomData,
+            }),
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Ref<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// A wrapper type for a mutably borrowed value from a `RefCell<T>`.
+pub struct RefMut<'b, T: ?Sized> {
+    value: NonNull<T>,
+    borrow: BorrowRefMut<'b>,
+    // `NonNull` is covariant over `T`, but a `RefMut` has to be invariant,
+    // just like `&'b mut T`.
+    marker: PhantomData<&'b mut T>,
+}
+
+impl<T: ?Sized> Deref for RefMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: a `RefMut` only exists while the state is `Exclusive`, and
+        // the `RefMut`s split off from it point to disjoint data.
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: see `deref`.
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<'b, T: ?Sized> RefMut<'b, T> {
+    /// Makes a new `RefMut` for a component of the borrowed data.
+    pub fn map<U: ?Sized, F>(mut orig: RefMut<'b, T>, f: F) -> RefMut<'b, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        RefMut {
+            value: NonNull::from(f(&mut *orig)),
+            borrow: orig.borrow,
+            marker: PhantomData,
+        }
+    }
+
+    /// Makes a new `RefMut` for an optional component of the borrowed data, or
+    /// returns the original guard if `f` returns `None`.
+    pub fn filter_map<U: ?Sized, F>(
+        mut orig: RefMut<'b, T>,
+        f: F,
+    ) -> Result<RefMut<'b, U>, RefMut<'b, T>>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        match f(&mut *orig).map(NonNull::from) {
+            Some(value) => Ok(RefMut {
+                value,
+                borrow: orig.borrow,
+                marker: PhantomData,
+            }),
+            None => Err(orig),
+        }
+    }
+
+    /// Splits a `RefMut` into multiple `RefMut`s for different components of
+    /// the borrowed data. The value stays exclusively borrowed until both
+    /// returned guards go out of scope.
+    pub fn map_split<U: ?Sized, V: ?Sized, F>(
+        mut orig: RefMut<'b, T>,
+        f: F,
+    ) -> (RefMut<'b, U>, RefMut<'b, V>)
+    where
+        F: FnOnce(&mut T) -> (&mut U, &mut V),
+    {
+        let (a, b) = f(&mut *orig);
+        let (a, b) = (NonNull::from(a), NonNull::from(b));
+        let borrow = orig.borrow.split();
+        (
+            RefMut {
+                value: a,
+                borrow,
+                marker: PhantomData,
+            },
+            RefMut {
+                value: b,
+                borrow: orig.borrow,
+                marker: PhantomData,
+            },
+        )
+    }
+}
+
+impl<'b, T: ?Sized> RefMut<'b, T> {
+    /// Turns a `RefMut` into a `Ref` without letting any other mutable
+    /// borrow in between.
+    /// panic if `orig` was split off by `RefMut::map_split`.
+    pub fn downgrade(orig: RefMut<'b, T>) -> Ref<'b, T> {
+        Ref {
+            value: orig.value,
+            borrow: orig.borrow.downgrade(),
+            upgradable: true,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RefMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// A thread-safe counterpart of `RefCell`: a reader-writer lock that follows
+/// the same `BorrowState` model.
+///
+/// Any number of readers (`Shared(n)`) or a single writer (`Exclusive`) can
+/// hold the value at a time. Instead of panicking on a conflicting borrow,
+/// `read` and `write` wait until the conflicting guards are dropped. If a
+/// thread panics while holding a writer guard, the cell is poisoned.
+///
+/// Writers are preferred: while one is waiting, no new reader gets in, so a
+/// steady stream of readers can't starve it. A steady stream of writers can
+/// starve readers instead. Waiting is a busy loop that spins and yields, not
+/// a park, so only hold the guards briefly.
+pub struct RwCell<T> {
+    state: AtomicUsize,
+    // The number of threads blocked in `write`.
+    writers_waiting: AtomicUsize,
+    poisoned: AtomicBool,
+    inner: UnsafeCell<T>,
+}
+
+// SAFETY: the `state` protocol hands out `&mut T` to one thread at a time,
+// and `&T` only to readers while no writer exists.
+unsafe impl<T: Send> Send for RwCell<T> {}
+unsafe impl<T: Send + Sync> Sync for RwCell<T> {}
+
+/// The value of `RwCell::state` while a writer holds the value; any other
+/// value is the number of readers.
+const WRITER: usize = usize::MAX;
+
+impl<T> RwCell<T> {
+    pub fn new(value: T) -> RwCell<T> {
+        RwCell {
+            state: AtomicUsize::new(0),
+            writers_waiting: AtomicUsize::new(0),
+            poisoned: AtomicBool::new(false),
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns the current borrow state. Other threads may change it at any
+    /// time, so this is only a snapshot.
+    pub fn state(&self) -> BorrowState {
+        match self.state.load(Ordering::Relaxed) {
+            0 => BorrowState::Unused,
+            WRITER => BorrowState::Exclusive,
+            n => BorrowState::Shared(n),
+        }
+    }
+
+    /// Acquires shared read access, blocking the current thread while a
+    /// writer holds the value or waits for it.
+    ///
+    /// Taking a second read guard on a thread that already holds one can
+    /// deadlock, if a writer starts waiting in between.
+    ///
+    /// Returns an error, which still carries the guard, if the cell is poisoned.
+    pub fn read(&self) -> LockResult<RwRef<'_, T>> {
+        loop {
+            match self.try_read() {
+                Ok(guard) => return Ok(guard),
+                Err(TryLockError::Poisoned(err)) => return Err(err),
+                Err(TryLockError::WouldBlock) => backoff(),
+            }
+        }
+    }
+
+    /// Attempts to acquire shared read access without blocking. Fails while
+    /// a writer holds the value or waits for it.
+    pub fn try_read(&self) -> TryLockResult<RwRef<'_, T>> {
+        if self.writers_waiting.load(Ordering::Relaxed) > 0 {
+            return Err(TryLockError::WouldBlock);
+        }
+
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            match state {
+                WRITER => return Err(TryLockError::WouldBlock),
+                n if n == WRITER - 1 => panic!("too many readers"),
+                n => match self.state.compare_exchange_weak(
+                    n,
+                    n + 1,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(actual) => state = actual,
+                },
+            }
+        }
+
+        self.poison_check(RwRef { cell: self })
+            .map_err(TryLockError::Poisoned)
+    }
+
+    /// Acquires exclusive write access, blocking the current thread while
+    /// any reader or writer holds the value. New readers are held off in the
+    /// meantime.
+    ///
+    /// Returns an error, which still carries the guard, if the cell is poisoned.
+    pub fn write(&self) -> LockResult<RwRefMut<'_, T>> {
+        self.writers_waiting.fetch_add(1, Ordering::Relaxed);
+        let _waiting = WaitingWriter(&self.writers_waiting);
+        loop {
+            match self.try_write() {
+                Ok(guard) => return Ok(guard),
+                Err(TryLockError::Poisoned(err)) => return Err(err),
+                Err(TryLockError::WouldBlock) => backoff(),
+            }
+        }
+    }
+
+    /// Attempts to acquire exclusive write access without blocking.
+    pub fn try_write(&self) -> TryLockResult<RwRefMut<'_, T>> {
+        if self
+            .state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(TryLockError::WouldBlock);
+        }
+
+        let guard = RwRefMut {
+            cell: self,
+            panicking: thread::panicking(),
+        };
+        self.poison_check(guard).map_err(TryLockError::Poisoned)
+    }
+
+    /// Determines whether a writer panicked while holding the value.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Clears the poisoned state, e.g. after the value was checked and repaired.
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    /// Consumes the cell, returning the wrapped value.
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.is_poisoned();
+        let value = self.inner.into_inner();
+        if poisoned {
+            Err(PoisonError::new(value))
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    /// This call borrows `RwCell` mutably, so no locking is needed.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.is_poisoned();
+        let value = self.inner.get_mut();
+        if poisoned {
+            Err(PoisonError::new(value))
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn poison_check<G>(&self, guard: G) -> LockResult<G> {
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+}
+
+/// Counts a thread blocked in `RwCell::write` for as long as it lives.
+struct WaitingWriter<'a>(&'a AtomicUsize);
+
+impl Drop for WaitingWriter<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Waits a little before retrying to take a contended lock.
+fn backoff() {
+    hint::spin_loop();
+    thread::yield_now();
+}
+
+/// A guard for shared read access to the value of a `RwCell<T>`.
+pub struct RwRef<'a, T> {
+    cell: &'a RwCell<T>,
+}
+
+impl<T> Deref for RwRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: a reader exists, so no writer can.
+        unsafe { &*self.cell.inner.get() }
+    }
+}
+
+impl<T> Drop for RwRef<'_, T> {
+    fn drop(&mut self) {
+        self.cell.state.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RwRef<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// A guard for exclusive write access to the value of a `RwCell<T>`.
+pub struct RwRefMut<'a, T> {
+    cell: &'a RwCell<T>,
+    // Whether the thread was already panicking when the guard was created.
+    panicking: bool,
+}
+
+impl<T> Deref for RwRefMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: we are the only writer, and no reader exists.
+        unsafe { &*self.cell.inner.get() }
+    }
+}
+
+impl<T> DerefMut for RwRefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: see `deref`.
+        unsafe { &mut *self.cell.inner.get() }
+    }
+}
+
+impl<T> Drop for RwRefMut<'_, T> {
+    fn drop(&mut self) {
+        if !self.panicking && thread::panicking() {
+            self.cell.poisoned.store(true, Ordering::Relaxed);
+        }
+        self.cell.state.store(0, Ordering::Release);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RwRefMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// Opt-in recording of `RefCell` borrows, and an ASCII rendering of how they
+/// overlap over time.
+///
+/// Recording is per thread (a `RefCell` never leaves its thread while it is
+/// borrowed) and only covers borrows taken between `start` and `stop`.
+#[cfg(feature = "borrow-timeline")]
+pub mod timeline {
+    use super::BorrowState;
+    use crate::s3_cell::Cell;
+    use std::{fmt::Write, panic::Location};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum BorrowKind {
+        Shared,
+        Exclusive,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Action {
+        Acquire,
+        Release,
+        /// A borrow that failed because of a conflicting one.
+        Conflict,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct BorrowEvent {
+        /// The logical timestamp, i.e. the position of the event in the recording.
+        pub time: usize,
+        /// The address of the cell, which identifies it while it is alive.
+        pub cell: usize,
+        pub kind: BorrowKind,
+        pub action: Action,
+        /// The time the borrow was acquired, which pairs a `Release` with its
+        /// `Acquire`. Equal to `time` for the other actions.
+        pub borrow: usize,
+        /// Where the borrow was taken.
+        pub location: &'static Location<'static>,
+    }
+
+    thread_local! {
+        static EVENTS: Cell<Option<Vec<BorrowEvent>>> = const { Cell::new(None) };
+    }
+
+    /// Starts recording borrows on the current thread, discarding any
+    /// unfinished recording.
+    pub fn start() {
+        EVENTS.with(|events| events.set(Some(Vec::new())));
+    }
+
+    /// Stops recording and returns the events recorded since `start`.
+    pub fn stop() -> Vec<BorrowEvent> {
+        EVENTS.with(|events| events.take()).unwrap_or_default()
+    }
+
+    /// Records an event at the caller's location, and returns its timestamp
+    /// if a recording is running.
+    #[track_caller]
+    pub(super) fn record(
+        state: &Cell<BorrowState>,
+        kind: BorrowKind,
+        action: Action,
+        borrow: Option<usize>,
+    ) -> Option<usize> {
+        record_at(state, kind, action, borrow, Location::caller())
+    }
+
+    pub(super) fn record_at(
+        state: &Cell<BorrowState>,
+        kind: BorrowKind,
+        action: Action,
+        borrow: Option<usize>,
+        location: &'static Location<'static>,
+    ) -> Option<usize> {
+        EVENTS.with(|events| {
+            events.with_mut(|events| {
+                let events = events.as_mut()?;
+                let time = events.len();
+                events.push(BorrowEvent {
+                    time,
+                    cell: state as *const Cell<BorrowState> as usize,
+                    kind,
+                    action,
+                    borrow: borrow.unwrap_or(time),
+                    location,
+                });
+                Some(time)
+            })
+        })
+    }
+
+    /// Renders one lane per borrow, grouped by cell in order of first use.
+    ///
+    /// Shared borrows are drawn as `[---]`, exclusive ones as `[===]`, a
+    /// borrow still held at the end of the recording ends in `>`, and a
+    /// failed borrow is an `X` at the time it was attempted:
+    ///
+    /// ```text
+    ///                                    0123
+    /// cell #0
+    ///   shared    src/main.rs:10:18      [-]
+    ///   exclusive src/main.rs:12:18        X
+    /// ```
+    pub fn render(events: &[BorrowEvent]) -> String {
+        let mut cells: Vec<usize> = Vec::new();
+        for event in events {
+            if !cells.contains(&event.cell) {
+                cells.push(event.cell);
+            }
+        }
+
+        let label = |event: &BorrowEvent| {
+            let kind = match event.kind {
+                BorrowKind::Shared => "shared",
+                BorrowKind::Exclusive => "exclusive",
+            };
+            format!("{:<9} {}", kind, event.location)
+        };
+        let width = events
+            .iter()
+            .map(|event| label(event).len())
+            .max()
+            .unwrap_or(0);
+
+        let mut out = String::new();
+        let ruler: String = (0..events.len())
+            .map(|t| char::from_digit((t % 10) as u32, 10).unwrap())
+            .collect();
+        writeln!(out, "  {:width$}  {}", "", ruler).unwrap();
+
+        for (i, &cell) in cells.iter().enumerate() {
+            writeln!(out, "cell #{}", i).unwrap();
+
+            let starts = events
+                .iter()
+                .filter(|e| e.cell == cell && e.action != Action::Release);
+            for start in starts {
+                let end = events
+                    .iter()
+                    .find(|e| e.action == Action::Release && e.borrow == start.time)
+                    .map(|e| e.time);
+                let fill = match start.kind {
+                    BorrowKind::Shared => '-',
+                    BorrowKind::Exclusive => '=',
+                };
+
+                let lane: String = (0..events.len())
+                    .map(|t| match (start.action, end) {
+                        (Action::Conflict, _) if t == start.time => 'X',
+                        (Action::Conflict, _) => ' ',
+                        _ if t < start.time => ' ',
+                        _ if t == start.time => '[',
+                        (_, Some(end)) if t < end => fill,
+                        (_, Some(end)) if t == end => ']',
+                        (_, Some(_)) => ' ',
+                        (_, None) if t == events.len() - 1 => '>',
+                        (_, None) => fill,
+                    })
+                    .collect();
+                writeln!(out, "  {:width$}  {}", label(start), lane.trim_end()).unwrap();
+            }
+        }
+        out
     }
 }
 
@@ -50,7 +1379,6 @@ impl<T> RefCell<T> {
 mod tests {
     use super::*;
 
-    #[cfg(feature = "skip")]
     #[test]
     fn create_refcell() {
         let rf = RefCell::new(42);
@@ -60,7 +1388,25 @@ mod tests {
         assert_eq!(rf.state.get(), BorrowState::Unused);
     }
 
-    #[cfg(feature = "skip")]
+    #[test]
+    fn debug() {
+        let c = RefCell::new(42);
+        assert_eq!(format!("{:?}", c), "RefCell { value: 42, state: Unused }");
+
+        let b = c.borrow();
+        assert_eq!(
+            format!("{:?}", c),
+            "RefCell { value: 42, state: Shared(1) }"
+        );
+        drop(b);
+
+        let m = c.borrow_mut();
+        assert_eq!(
+            format!("{:?}", c),
+            "RefCell { value: <borrowed>, state: Exclusive }"
+        );
+    }
+
     #[test]
     fn borrow_many_times() {
         let rc = RefCell::new(42);
@@ -70,7 +1416,6 @@ mod tests {
         assert_eq!(rc.state.get(), BorrowState::Shared(2));
     }
 
-    #[cfg(feature = "skip")]
     #[test]
     fn borrow_mut_once() {
         let rc = RefCell::new(42);
@@ -79,7 +1424,6 @@ mod tests {
         assert_eq!(rc.state.get(), BorrowState::Exclusive);
     }
 
-    #[cfg(feature = "skip")]
     #[test]
     #[should_panic(expected = "already mutably borrowed")]
     fn borrow_panic() {
@@ -89,7 +1433,15 @@ mod tests {
         let b = c.borrow(); // this causes a panic
     }
 
-    #[cfg(feature = "skip")]
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn borrow_mut_panic() {
+        let c = RefCell::new(42);
+
+        let b = c.borrow();
+        let m = c.borrow_mut(); // this causes a panic
+    }
+
     #[test]
     fn borrow_mut_after_all_borrows_expires() {
         let rc = RefCell::new(42);
@@ -102,7 +1454,6 @@ mod tests {
         assert_eq!(rc.state.get(), BorrowState::Exclusive);
     }
 
-    #[cfg(feature = "skip")]
     #[test]
     fn borrow_mut() {
         let c = RefCell::new("hello".to_owned());
@@ -112,6 +1463,421 @@ mod tests {
         assert_eq!(&*c.borrow(), "bonjour");
     }
 
+    #[test]
+    fn try_borrow() {
+        let c = RefCell::new(42);
+        {
+            let m = c.borrow_mut();
+            let err = c.try_borrow().unwrap_err();
+
+            assert_eq!(err.state(), BorrowState::Exclusive);
+            assert!(err
+                .to_string()
+                .starts_with("already mutably borrowed: an exclusive borrow is active"));
+        }
+        assert_eq!(*c.try_borrow().unwrap(), 42);
+    }
+
+    #[test]
+    fn try_borrow_mut() {
+        let c = RefCell::new(42);
+        {
+            let b1 = c.borrow();
+            let b2 = c.borrow();
+            let err = c.try_borrow_mut().unwrap_err();
+
+            assert_eq!(err.state(), BorrowState::Shared(2));
+            assert!(err
+                .to_string()
+                .starts_with("already borrowed: 2 shared borrows are active"));
+        }
+        *c.try_borrow_mut().unwrap() += 1;
+        assert_eq!(*c.borrow(), 43);
+    }
+
+    #[cfg(feature = "debug-borrows")]
+    #[test]
+    fn borrow_error_reports_location() {
+        let c = RefCell::new(42);
+
+        let b1 = c.borrow();
+        let (b2, line) = (c.borrow(), line!());
+        drop(b1);
+
+        let err = c.try_borrow_mut().unwrap_err();
+        let held_at = err.held_at().unwrap();
+        assert_eq!((held_at.file(), held_at.line()), (file!(), line));
+        assert!(err
+            .to_string()
+            .ends_with(&format!("(borrowed at {})", held_at)));
+    }
+
+    #[test]
+    fn downgrade() {
+        let c = RefCell::new(vec![1]);
+
+        let mut m = c.borrow_mut();
+        m.push(2);
+        let r = RefMut::downgrade(m);
+        assert_eq!(c.state.get(), BorrowState::Shared(1));
+
+        let r2 = c.borrow();
+        assert_eq!(*r, *r2);
+        assert!(c.try_borrow_mut().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot downgrade a split RefMut")]
+    fn downgrade_split() {
+        let c = RefCell::new((1, 2));
+
+        let (a, b) = RefMut::map_split(c.borrow_mut(), |(a, b)| (a, b));
+        let a = RefMut::downgrade(a); // this causes a panic
+    }
+
+    #[test]
+    fn try_upgrade() {
+        let c = RefCell::new(42);
+
+        let r = c.borrow();
+        let r2 = c.borrow();
+        let r = Ref::try_upgrade(r).unwrap_err();
+        drop(r2);
+
+        let mut m = Ref::try_upgrade(r).unwrap();
+        *m += 1;
+        assert_eq!(c.state.get(), BorrowState::Exclusive);
+        drop(m);
+
+        assert_eq!(*c.borrow(), 43);
+        assert_eq!(c.state.get(), BorrowState::Unused);
+    }
+
+    #[test]
+    fn try_upgrade_mapped() {
+        let c = RefCell::new(String::from("hello"));
+
+        let r = Ref::map(c.borrow(), |_| "static");
+        assert!(Ref::try_upgrade(r).is_err());
+
+        // a downgraded `RefMut` was obtained mutably, so it may go back
+        let m = RefMut::map(c.borrow_mut(), |s| s.as_mut_str());
+        let mut m = Ref::try_upgrade(RefMut::downgrade(m)).unwrap();
+        m.make_ascii_uppercase();
+        drop(m);
+        assert_eq!(*c.borrow(), "HELLO");
+    }
+
+    #[test]
+    fn try_borrow_ignores_conflict_policy() {
+        let c = RefCell::with_policy(42, ConflictPolicy::Panic);
+
+        let m = c.borrow_mut();
+        let err = c.try_borrow().unwrap_err();
+        assert_eq!(err.state(), BorrowState::Exclusive);
+        assert!(c.try_borrow_mut().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "already mutably borrowed")]
+    fn conflict_policy_panic() {
+        let c = RefCell::new(42);
+
+        let m = c.borrow_mut();
+        let b = c.borrow_checked(); // this causes a panic
+    }
+
+    #[test]
+    fn conflict_policy_hook() {
+        use std::panic::{self, AssertUnwindSafe};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CONFLICTS: AtomicUsize = AtomicUsize::new(0);
+        fn count(err: &(dyn Error + 'static)) {
+            assert!(err.is::<BorrowMutError>());
+            CONFLICTS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let c = RefCell::with_policy(42, ConflictPolicy::Hook(count));
+        let b = c.borrow();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| c.borrow_mut_checked().is_err()));
+
+        assert_eq!(CONFLICTS.load(Ordering::Relaxed), 1);
+        // the hook policy only panics in debug builds
+        assert_eq!(result.is_err(), cfg!(debug_assertions));
+    }
+
+    #[test]
+    fn try_borrow_many_mut_calls_hook() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CONFLICTS: AtomicUsize = AtomicUsize::new(0);
+        fn count(err: &(dyn Error + 'static)) {
+            assert!(err.is::<BorrowMutError>());
+            CONFLICTS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let a = RefCell::new(1);
+        let b = RefCell::with_policy(2, ConflictPolicy::Hook(count));
+        let held = b.borrow();
+
+        let err = RefCell::try_borrow_many_mut([&a, &b]).unwrap_err();
+        assert!(matches!(err, BorrowManyError::Busy { index: 1, .. }));
+        assert_eq!(CONFLICTS.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn borrow_many_mut() {
+        use std::rc::Rc;
+
+        let nodes: Vec<_> = (0..3).map(|i| Rc::new(RefCell::new(i))).collect();
+        {
+            let [mut a, mut b] = RefCell::borrow_many_mut([&*nodes[0], &*nodes[2]]);
+            mem::swap(&mut *a, &mut *b);
+        }
+        assert_eq!(*nodes[0].borrow(), 2);
+        assert_eq!(*nodes[2].borrow(), 0);
+    }
+
+    #[test]
+    fn try_borrow_many_mut_is_all_or_nothing() {
+        let (a, b, c) = (RefCell::new(1), RefCell::new(2), RefCell::new(3));
+
+        let held = c.borrow();
+        let err = RefCell::try_borrow_many_mut([&a, &b, &c]).unwrap_err();
+        assert!(matches!(err, BorrowManyError::Busy { index: 2, .. }));
+        assert!(err.to_string().starts_with("cell #2: already borrowed"));
+        assert_eq!(a.state.get(), BorrowState::Unused);
+        assert_eq!(b.state.get(), BorrowState::Unused);
+
+        let err = RefCell::try_borrow_many_mut([&a, &b, &a]).unwrap_err();
+        assert_eq!(
+            err,
+            BorrowManyError::Duplicate {
+                first: 0,
+                second: 2
+            }
+        );
+        assert_eq!(a.state.get(), BorrowState::Unused);
+    }
+
+    #[test]
+    fn replace() {
+        let c = RefCell::new(5);
+        assert_eq!(c.replace(6), 5);
+        assert_eq!(c.replace_with(|&mut old| old + 1), 6);
+        assert_eq!(c.take(), 7);
+        assert_eq!(c.into_inner(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn replace_while_borrowed() {
+        let c = RefCell::new(5);
+
+        let b = c.borrow();
+        c.replace(6); // this causes a panic
+    }
+
+    #[test]
+    fn swap() {
+        let c1 = RefCell::new(5);
+        let c2 = RefCell::new(6);
+        c1.swap(&c2);
+
+        assert_eq!((*c1.borrow(), *c2.borrow()), (6, 5));
+    }
+
+    #[test]
+    fn get_mut_and_undo_leak() {
+        let mut c = RefCell::new(5);
+        *c.get_mut() += 1;
+
+        std::mem::forget(c.borrow_mut());
+        assert!(c.try_borrow().is_err());
+
+        *c.undo_leak() += 1;
+        assert_eq!(c.state.get(), BorrowState::Unused);
+        assert_eq!(*c.borrow(), 7);
+    }
+
+    #[test]
+    fn ref_map() {
+        use std::collections::HashMap;
+
+        fn population<'a>(map: &'a RefCell<HashMap<&str, i32>>, key: &str) -> Option<Ref<'a, i32>> {
+            Ref::filter_map(map.borrow(), |map| map.get(key)).ok()
+        }
+
+        let map = RefCell::new(HashMap::from([("kyoto", 11837), ("marbles", 38)]));
+        let kyoto = population(&map, "kyoto").unwrap();
+        assert!(population(&map, "atlantis").is_none());
+        assert_eq!(*kyoto, 11837);
+        assert_eq!(map.state.get(), BorrowState::Shared(1));
+
+        drop(kyoto);
+        assert_eq!(map.state.get(), BorrowState::Unused);
+
+        let pair = RefCell::new((String::from("hello"), 42));
+        let (s, n) = Ref::map_split(pair.borrow(), |(s, n)| (s.as_str(), n));
+        assert_eq!((&*s, *n), ("hello", 42));
+        assert_eq!(pair.state.get(), BorrowState::Shared(2));
+    }
+
+    #[test]
+    fn ref_mut_map() {
+        let c = RefCell::new((String::from("hello"), 42));
+        {
+            let mut n = RefMut::map(c.borrow_mut(), |(_, n)| n);
+            *n += 1;
+        }
+        assert_eq!(c.borrow().1, 43);
+
+        let c = RefCell::new(vec![1, 2, 3, 4]);
+        {
+            let (mut left, mut right) = RefMut::map_split(c.borrow_mut(), |v| v.split_at_mut(2));
+            left.swap(0, 1);
+            drop(left);
+            // the exclusive borrow lives on in `right`
+            assert!(c.try_borrow().is_err());
+            right[1] = 0;
+        }
+        assert_eq!(c.state.get(), BorrowState::Unused);
+        assert_eq!(*c.borrow(), [2, 1, 3, 0]);
+    }
+
+    #[test]
+    fn rw_cell() {
+        let c = RwCell::new(42);
+        {
+            let r1 = c.read().unwrap();
+            let r2 = c.try_read().unwrap();
+            assert_eq!(c.state(), BorrowState::Shared(2));
+            assert!(matches!(c.try_write(), Err(TryLockError::WouldBlock)));
+            assert_eq!(*r1 + *r2, 84);
+        }
+        {
+            let mut w = c.write().unwrap();
+            *w += 1;
+            assert_eq!(c.state(), BorrowState::Exclusive);
+            assert!(matches!(c.try_read(), Err(TryLockError::WouldBlock)));
+        }
+        assert_eq!(c.state(), BorrowState::Unused);
+        assert_eq!(c.into_inner().unwrap(), 43);
+    }
+
+    #[test]
+    fn rw_cell_is_sync() {
+        use std::sync::Arc;
+
+        let c = Arc::new(RwCell::new(0));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let c = Arc::clone(&c);
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        *c.write().unwrap() += 1;
+                        assert!(*c.read().unwrap() > 0);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(*c.read().unwrap(), 4000);
+    }
+
+    #[test]
+    fn rw_cell_readers_do_not_starve_writer() {
+        use std::sync::atomic::AtomicBool;
+        use std::sync::Arc;
+
+        let c = Arc::new(RwCell::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        // Overlapping readers, so that the cell is never free on its own.
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (c, stop) = (Arc::clone(&c), Arc::clone(&stop));
+                thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        let _r = c.read().unwrap();
+                        thread::sleep(std::time::Duration::from_micros(100));
+                    }
+                })
+            })
+            .collect();
+
+        thread::sleep(std::time::Duration::from_millis(10));
+        *c.write().unwrap() += 1;
+        stop.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(*c.read().unwrap(), 1);
+    }
+
+    #[test]
+    fn rw_cell_poison() {
+        use std::sync::Arc;
+
+        let c = Arc::new(RwCell::new(42));
+        let c2 = Arc::clone(&c);
+        let result = thread::spawn(move || {
+            let _w = c2.write().unwrap();
+            panic!("writer panicked");
+        })
+        .join();
+
+        assert!(result.is_err());
+        assert!(c.is_poisoned());
+        assert_eq!(c.state(), BorrowState::Unused);
+
+        let r = c.read().unwrap_err().into_inner();
+        assert_eq!(*r, 42);
+        drop(r);
+
+        c.clear_poison();
+        assert!(c.try_write().is_ok());
+    }
+
+    #[cfg(feature = "borrow-timeline")]
+    #[test]
+    fn borrow_timeline() {
+        let c = RefCell::new(42);
+        let other = RefCell::new(0);
+
+        timeline::start();
+        let b1 = c.borrow(); // 0
+        let b2 = Ref::clone(&b1); // 1
+        drop(b1); // 2
+        assert!(c.try_borrow_mut().is_err()); // 3
+        *other.borrow_mut() += 1; // 4, 5
+        let events = timeline::stop();
+        assert_eq!(events.len(), 6);
+
+        let kinds: Vec<_> = events.iter().map(|e| (e.kind, e.action)).collect();
+        assert_eq!(
+            kinds[3],
+            (timeline::BorrowKind::Exclusive, timeline::Action::Conflict)
+        );
+        assert_eq!((events[2].borrow, events[5].borrow), (0, 4));
+
+        let rendered = timeline::render(&events);
+        let offset = rendered.find('0').unwrap();
+        let lanes: Vec<_> = rendered
+            .lines()
+            .map(|line| line.get(offset..).unwrap_or(line))
+            .collect();
+        assert_eq!(
+            lanes,
+            ["012345", "cell #0", "[-]", " [--->", "   X", "cell #1", "    []"]
+        );
+        println!("{}", rendered);
+    }
+
     #[test]
     fn refcell_demo() {
         use std::cell::{RefCell, RefMut};
@@ -1,7 +1,27 @@
 use crate::delim;
 use crate::s3_cell::Cell;
 use crate::s4_refcell::RefCell;
-use std::{fmt, marker::PhantomData, ops::Deref, ptr::NonNull};
+use std::{
+    alloc::{self, Layout},
+    borrow, cmp, fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    process,
+    ptr::{self, NonNull},
+};
+
+// `repr(C)` keeps `value` last, so that `Rc::from_raw` and the allocation of
+// unsized values can compute where it starts.
+#[repr(C)]
+struct RcBox<T: ?Sized> {
+    strong: Cell<usize>,
+    // All strong references together own one weak reference, which keeps the
+    // allocation alive while the value is.
+    weak: Cell<usize>,
+    value: T,
+}
 
 /**
  * Rc<T>, the Reference Counted Smart Pointer
@@ -14,39 +34,655 @@ use std::{fmt, marker::PhantomData, ops::Deref, ptr::NonNull};
  *
  * Note that `Rc<T>` is only for use in single-threaded scenarios.
  */
-
-#[derive(Debug)]
-struct Rc<T: fmt::Debug> {
-    _phantom: PhantomData<T>,
+pub struct Rc<T: ?Sized> {
+    ptr: NonNull<RcBox<T>>,
+    // `Rc<T>` owns an `RcBox<T>` as far as the drop checker is concerned.
+    _phantom: PhantomData<RcBox<T>>,
 }
 
-impl<T: fmt::Debug> Rc<T> {
+impl<T> Rc<T> {
     fn new(value: T) -> Self {
-        /*
-         * Todo
-         */
-        todo!()
+        let boxed = Box::new(RcBox {
+            strong: Cell::new(1),
+            weak: Cell::new(1),
+            value,
+        });
+
+        Rc {
+            // SAFETY: `Box::into_raw` never returns a null pointer.
+            ptr: unsafe { NonNull::new_unchecked(Box::into_raw(boxed)) },
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Creates an `Rc` to a value that can hold a `Weak` to itself.
+    ///
+    /// `data_fn` gets a `Weak` to the allocation before the value is in it,
+    /// so upgrading it returns `None` until `new_cyclic` returns.
+    fn new_cyclic<F>(data_fn: F) -> Self
+    where
+        F: FnOnce(&Weak<T>) -> T,
+    {
+        let uninit = Box::new(RcBox {
+            strong: Cell::new(0),
+            weak: Cell::new(1),
+            value: mem::MaybeUninit::<T>::uninit(),
+        });
+        // `RcBox` is `repr(C)`, and `MaybeUninit<T>` has the layout of `T`.
+        let ptr = NonNull::from(Box::leak(uninit)).cast::<RcBox<T>>();
+
+        let weak = Weak { ptr };
+        let value = data_fn(&weak);
+
+        // SAFETY: the allocation is alive since `weak` is, and the value is
+        // neither initialized nor reachable yet, as the strong count is 0.
+        unsafe { ptr::write(ptr::addr_of_mut!((*ptr.as_ptr()).value), value) };
+
+        // The strong references take over the weak reference we made.
+        mem::forget(weak);
+        let rc = Rc {
+            ptr,
+            _phantom: PhantomData,
+        };
+        rc.inner().strong.set(1);
+        rc
+    }
+
+    /// Returns a mutable reference to the value, cloning it into a new
+    /// allocation first if other `Rc`s share it (clone-on-write).
+    ///
+    /// If only `Weak`s share it, the value is moved into a new allocation
+    /// instead, and those `Weak`s will no longer upgrade.
+    fn make_mut(this: &mut Self) -> &mut T
+    where
+        T: Clone,
+    {
+        if Rc::strong_count(this) > 1 {
+            *this = Rc::new((**this).clone());
+        } else if Rc::weak_count(this) > 0 {
+            let inner = this.inner();
+            inner.strong.set(0);
+
+            // SAFETY: we were the only strong reference and have just given
+            // it up, so the value is moved out exactly once and the `Weak`s
+            // see it as dropped.
+            let value = unsafe { ptr::read(&inner.value) };
+            let old = mem::replace(this, Rc::new(value));
+            drop(Weak { ptr: old.ptr });
+            mem::forget(old);
+        }
+
+        Rc::get_mut(this).expect("`this` was made unique above")
+    }
+
+    /// Returns the value if this is the only `Rc` to it, or gives the `Rc`
+    /// back otherwise. Outstanding `Weak`s will no longer upgrade.
+    fn try_unwrap(this: Self) -> Result<T, Self> {
+        if Rc::strong_count(&this) != 1 {
+            return Err(this);
+        }
+
+        let this = mem::ManuallyDrop::new(this);
+        this.inner().strong.set(0);
+
+        // SAFETY: we were the only strong reference and have just given it
+        // up, so the value is moved out exactly once.
+        let value = unsafe { ptr::read(&this.inner().value) };
+        drop(Weak { ptr: this.ptr });
+        Ok(value)
     }
 
+    /// Returns the value if this is the only `Rc` to it, and drops the `Rc`
+    /// otherwise.
+    fn into_inner(this: Self) -> Option<T> {
+        Rc::try_unwrap(this).ok()
+    }
+
+    /// Returns the value if this is the only `Rc` to it, or a clone of it
+    /// otherwise.
+    fn unwrap_or_clone(this: Self) -> T
+    where
+        T: Clone,
+    {
+        Rc::try_unwrap(this).unwrap_or_else(|rc| (*rc).clone())
+    }
+}
+
+impl<T: ?Sized> Rc<T> {
     fn strong_count(this: &Self) -> usize {
-        /*
-         * TODO
-         */
-        todo!()
+        this.inner().strong.get()
+    }
+
+    fn weak_count(this: &Self) -> usize {
+        this.inner().weak.get() - 1
+    }
+
+    /// Creates a `Weak` pointer to this allocation.
+    fn downgrade(this: &Self) -> Weak<T> {
+        inc_count(&this.inner().weak);
+
+        Weak { ptr: this.ptr }
+    }
+
+    /// Returns a mutable reference to the value if there are no other `Rc`
+    /// or `Weak` pointers to it.
+    fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if Rc::is_unique(this) {
+            // SAFETY: nobody else can reach the value.
+            Some(unsafe { &mut (*this.ptr.as_ptr()).value })
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if both `Rc`s point to the same allocation.
+    fn ptr_eq(this: &Self, other: &Self) -> bool {
+        ptr::eq(this.ptr.as_ptr(), other.ptr.as_ptr())
+    }
+
+    /// Returns a raw pointer to the value, without touching the counts.
+    fn as_ptr(this: &Self) -> *const T {
+        // SAFETY: `ptr` points to a live `RcBox`; no reference is created.
+        unsafe { ptr::addr_of!((*this.ptr.as_ptr()).value) }
+    }
+
+    /// Consumes the `Rc`, returning a raw pointer to the value. The strong
+    /// reference is kept, and must be given back with `Rc::from_raw` (or
+    /// `Rc::decrement_strong_count`) to avoid a leak.
+    fn into_raw(this: Self) -> *const T {
+        let ptr = Rc::as_ptr(&this);
+        mem::forget(this);
+        ptr
+    }
+
+    /// Takes back the strong reference of a pointer returned by `Rc::into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Rc::<T>::into_raw`, and each strong reference
+    /// handed out that way must be taken back at most once.
+    unsafe fn from_raw(ptr: *const T) -> Self {
+        let offset = value_offset(mem::align_of_val(&*ptr));
+        // Moving the address back within the allocation keeps the metadata.
+        let rc_box = (ptr as *const RcBox<T>).byte_sub(offset);
+
+        Rc {
+            ptr: NonNull::new_unchecked(rc_box as *mut RcBox<T>),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Adds a strong reference through a pointer returned by `Rc::into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Rc::<T>::into_raw`, and the allocation must
+    /// still have a strong reference.
+    unsafe fn increment_strong_count(ptr: *const T) {
+        let rc = mem::ManuallyDrop::new(Rc::from_raw(ptr));
+        mem::forget(Rc::clone(&rc));
+    }
+
+    /// Gives up a strong reference through a pointer returned by `Rc::into_raw`,
+    /// dropping the value if it was the last one.
+    ///
+    /// # Safety
+    ///
+    /// Same as `Rc::from_raw`.
+    unsafe fn decrement_strong_count(ptr: *const T) {
+        drop(Rc::from_raw(ptr));
+    }
+
+    fn is_unique(this: &Self) -> bool {
+        Rc::strong_count(this) == 1 && Rc::weak_count(this) == 0
+    }
+
+    fn inner(&self) -> &RcBox<T> {
+        // SAFETY: the `RcBox` stays allocated as long as a strong reference
+        // to it exists, and we are one.
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// Moves the value behind `src` into a new allocation, bit by bit.
+    ///
+    /// # Safety
+    ///
+    /// `src` must point to a valid value, which the caller must not use or
+    /// drop afterwards.
+    unsafe fn copy_from_ptr(src: *const T) -> Self {
+        let value_layout = Layout::for_value(&*src);
+        let offset = value_offset(value_layout.align());
+        let layout = Layout::from_size_align(offset + value_layout.size(), value_layout.align())
+            .and_then(|layout| layout.align_to(mem::align_of::<RcBox<()>>()))
+            .expect("value is too large")
+            .pad_to_align();
+
+        let mem = alloc::alloc(layout);
+        if mem.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        let rc_box = set_data_ptr(src as *mut RcBox<T>, mem);
+
+        ptr::write(ptr::addr_of_mut!((*rc_box).strong), Cell::new(1));
+        ptr::write(ptr::addr_of_mut!((*rc_box).weak), Cell::new(1));
+        ptr::copy_nonoverlapping(
+            src.cast::<u8>(),
+            ptr::addr_of_mut!((*rc_box).value).cast::<u8>(),
+            value_layout.size(),
+        );
+        // `Weak::drop` frees the allocation with the layout of the `RcBox`.
+        debug_assert_eq!(Layout::for_value(&*rc_box), layout);
+
+        Rc {
+            ptr: NonNull::new_unchecked(rc_box),
+            _phantom: PhantomData,
+        }
     }
 }
 
-/*
- * TODO: Implement `Clone`
- */
+/// Adds one to a reference count, aborting if it would overflow: otherwise
+/// `mem::forget`ting clones in a loop could wrap it around and free the
+/// value while it is in use.
+fn inc_count(count: &Cell<usize>) {
+    match count.get().checked_add(1) {
+        Some(n) => count.set(n),
+        None => process::abort(),
+    }
+}
+
+/// Returns the offset of `value` in an `RcBox` whose value has the given alignment.
+fn value_offset(align: usize) -> usize {
+    let header = Layout::new::<RcBox<()>>();
+    header.size().next_multiple_of(align)
+}
+
+/// Replaces the address part of a (possibly fat) pointer, keeping its metadata.
+///
+/// Only for pointing at a new allocation, which `byte_sub` and friends can't
+/// do.
+///
+/// # Safety
+///
+/// Relies on the address being the first word of a fat pointer.
+unsafe fn set_data_ptr<T: ?Sized>(mut ptr: *mut T, data: *mut u8) -> *mut T {
+    ptr::write(ptr::addr_of_mut!(ptr).cast::<*mut u8>(), data);
+    ptr
+}
+
+impl<T> From<Vec<T>> for Rc<[T]> {
+    fn from(mut v: Vec<T>) -> Self {
+        // SAFETY: the elements are moved into the `Rc`, and `set_len` makes
+        // sure the `Vec` only frees its buffer without dropping them.
+        unsafe {
+            let rc = Rc::copy_from_ptr(v.as_slice() as *const [T]);
+            v.set_len(0);
+            rc
+        }
+    }
+}
+
+impl From<&str> for Rc<str> {
+    fn from(s: &str) -> Self {
+        // SAFETY: `str` has no destructor, so copying it leaves `s` usable.
+        unsafe { Rc::copy_from_ptr(s as *const str) }
+    }
+}
+
+impl From<String> for Rc<str> {
+    fn from(s: String) -> Self {
+        Rc::from(s.as_str())
+    }
+}
+
+impl<T: ?Sized> From<Box<T>> for Rc<T> {
+    fn from(b: Box<T>) -> Self {
+        let raw = Box::into_raw(b);
+        // SAFETY: the value is moved into the `Rc`, and `ManuallyDrop` makes
+        // sure the `Box` only frees its memory without dropping it.
+        unsafe {
+            let rc = Rc::copy_from_ptr(raw);
+            drop(Box::from_raw(raw as *mut mem::ManuallyDrop<T>));
+            rc
+        }
+    }
+}
+
+impl<T: ?Sized> Clone for Rc<T> {
+    fn clone(&self) -> Self {
+        inc_count(&self.inner().strong);
+
+        Rc {
+            ptr: self.ptr,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for Rc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner().value
+    }
+}
+
+impl<T: ?Sized> Drop for Rc<T> {
+    fn drop(&mut self) {
+        let inner = self.inner();
+        let strong = inner.strong.get() - 1;
+        inner.strong.set(strong);
+        if strong > 0 {
+            return;
+        }
+
+        // SAFETY: we were the last strong reference, so nobody can reach the
+        // value any more. `Weak::upgrade` fails from now on, so it is dropped
+        // exactly once, here.
+        unsafe { ptr::drop_in_place(&mut (*self.ptr.as_ptr()).value) };
+
+        // Give up the weak reference that all strong references shared, which
+        // frees the allocation unless other `Weak`s are left.
+        drop(Weak { ptr: self.ptr });
+    }
+}
+
+impl<T: fmt::Debug + ?Sized> fmt::Debug for Rc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
 
-/*
- * TODO: Implement `Deref`
+impl<T: fmt::Display + ?Sized> fmt::Display for Rc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+/// Formats the address of the value, like `{:p}` does for references.
+impl<T: ?Sized> fmt::Pointer for Rc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&ptr::addr_of!(**self), f)
+    }
+}
+
+// Comparisons and hashing look at the values, not at the allocations; use
+// `Rc::ptr_eq` to tell whether two `Rc`s share one.
+
+impl<T: PartialEq + ?Sized> PartialEq for Rc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Eq + ?Sized> Eq for Rc<T> {}
+
+impl<T: PartialOrd + ?Sized> PartialOrd for Rc<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<T: Ord + ?Sized> Ord for Rc<T> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: Hash + ?Sized> Hash for Rc<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+// Not imported, so that `rc.borrow()` keeps meaning `RefCell::borrow` here.
+impl<T: ?Sized> borrow::Borrow<T> for Rc<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized> AsRef<T> for Rc<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T: Default> Default for Rc<T> {
+    fn default() -> Self {
+        Rc::new(T::default())
+    }
+}
+
+impl<T> From<T> for Rc<T> {
+    fn from(value: T) -> Self {
+        Rc::new(value)
+    }
+}
+
+/**
+ * Weak<T>, a non-owning reference to an `Rc<T>` allocation
+ *
+ * A `Weak<T>` doesn't keep the value alive: once the last `Rc<T>` is gone, the
+ * value is dropped and `upgrade` returns `None`. The allocation itself (with
+ * the counters) is only freed once the last `Weak<T>` is gone as well.
  */
+pub struct Weak<T: ?Sized> {
+    // Dangling, and never dereferenced, for a `Weak` made by `Weak::new`.
+    ptr: NonNull<RcBox<T>>,
+}
+
+impl<T> Weak<T> {
+    /// Creates a `Weak` that never upgrades, without allocating.
+    fn new() -> Self {
+        Weak {
+            ptr: NonNull::new(ptr::without_provenance_mut(usize::MAX))
+                .expect("usize::MAX is not null"),
+        }
+    }
+}
+
+impl<T: ?Sized> Weak<T> {
+    /// Attempts to get an `Rc` to the value, or `None` if it has been dropped.
+    pub fn upgrade(&self) -> Option<Rc<T>> {
+        let inner = self.inner()?;
+        if inner.strong.get() == 0 {
+            return None;
+        }
+        inc_count(inner.strong);
+
+        Some(Rc {
+            ptr: self.ptr,
+            _phantom: PhantomData,
+        })
+    }
 
-/*
- * TODO: Implement `Drop`
+    fn strong_count(&self) -> usize {
+        self.inner().map_or(0, |inner| inner.strong.get())
+    }
+
+    /// Returns `true` if both `Weak`s point to the same allocation, or if
+    /// both were made by `Weak::new`.
+    fn ptr_eq(&self, other: &Self) -> bool {
+        ptr::eq(self.ptr.as_ptr(), other.ptr.as_ptr())
+    }
+
+    fn is_dangling(&self) -> bool {
+        self.ptr.as_ptr() as *mut () as usize == usize::MAX
+    }
+
+    /// Returns the counters, or `None` if there is no allocation.
+    ///
+    /// Unlike `Rc::inner`, this never hands out a reference to the value,
+    /// which may already have been dropped.
+    fn inner(&self) -> Option<WeakInner<'_>> {
+        if self.is_dangling() {
+            return None;
+        }
+
+        // SAFETY: the allocation stays alive as long as a weak reference to it
+        // exists, and we are one. We only create references to the counters.
+        let ptr = self.ptr.as_ptr();
+        unsafe {
+            Some(WeakInner {
+                strong: &*ptr::addr_of!((*ptr).strong),
+                weak: &*ptr::addr_of!((*ptr).weak),
+            })
+        }
+    }
+}
+
+/**
+ * UniqueRc<T>, an `Rc<T>` under construction
+ *
+ * A `UniqueRc<T>` is the only owner of its value, so it can be mutated without
+ * a `RefCell`. It can already hand out `Weak`s, which upgrade once `into_rc`
+ * has turned it into a shared `Rc<T>`. That closes reference cycles in one go:
+ * build the value, give the `Weak`s to whatever points back at it, then share.
+ *
+ * Once `Weak`s exist, writing through a `UniqueRc` is a form of shared
+ * mutability, so it can't be coerced to a shorter lifetime, which would let
+ * a short-lived value reach those `Weak`s:
+ *
+ * ```compile_fail,E0597
+ * use smart_pointers::s5_rc::UniqueRc;
+ *
+ * let unique: UniqueRc<&'static str> = UniqueRc::new("static");
+ * let weak = UniqueRc::downgrade(&unique);
+ * let local = String::from("local");
+ * let mut shorter: UniqueRc<&str> = unique;
+ * *shorter = local.as_str();
+ * std::mem::forget(UniqueRc::into_rc(shorter));
+ * drop(local);
+ * let s: &'static str = *weak.upgrade().unwrap();
+ * println!("{s}");
+ * ```
  */
+pub struct UniqueRc<T: ?Sized> {
+    ptr: NonNull<RcBox<T>>,
+    _phantom: PhantomData<RcBox<T>>,
+    // Invariant over `T`, since the `Weak`s handed out keep the original type.
+    _invariant: PhantomData<*mut T>,
+}
+
+impl<T> UniqueRc<T> {
+    pub fn new(value: T) -> Self {
+        // No strong references yet, so the `Weak`s we hand out can't upgrade,
+        // but we hold the weak reference the strong ones will share.
+        let boxed = Box::new(RcBox {
+            strong: Cell::new(0),
+            weak: Cell::new(1),
+            value,
+        });
+
+        UniqueRc {
+            // SAFETY: `Box::into_raw` never returns a null pointer.
+            ptr: unsafe { NonNull::new_unchecked(Box::into_raw(boxed)) },
+            _phantom: PhantomData,
+            _invariant: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> UniqueRc<T> {
+    /// Creates a `Weak` that upgrades once this is turned into an `Rc`.
+    pub fn downgrade(this: &Self) -> Weak<T> {
+        inc_count(&this.inner().weak);
+
+        Weak { ptr: this.ptr }
+    }
+
+    /// Shares the value, making the `Weak`s handed out so far upgradable.
+    pub fn into_rc(this: Self) -> Rc<T> {
+        this.inner().strong.set(1);
+        let this = mem::ManuallyDrop::new(this);
+
+        Rc {
+            ptr: this.ptr,
+            _phantom: PhantomData,
+        }
+    }
+
+    fn inner(&self) -> &RcBox<T> {
+        // SAFETY: we hold a weak reference, so the `RcBox` is allocated, and
+        // the value is only dropped by our own `Drop`.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: ?Sized> Deref for UniqueRc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner().value
+    }
+}
+
+impl<T: ?Sized> DerefMut for UniqueRc<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: `Weak`s can't upgrade while the strong count is 0, so
+        // nobody else can reach the value.
+        unsafe { &mut (*self.ptr.as_ptr()).value }
+    }
+}
+
+impl<T: ?Sized> Drop for UniqueRc<T> {
+    fn drop(&mut self) {
+        // SAFETY: the value was never shared, so we drop it exactly once.
+        unsafe { ptr::drop_in_place(&mut (*self.ptr.as_ptr()).value) };
+
+        drop(Weak { ptr: self.ptr });
+    }
+}
+
+impl<T: fmt::Debug + ?Sized> fmt::Debug for UniqueRc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// The counters of an `RcBox`, borrowed through a `Weak`.
+struct WeakInner<'a> {
+    strong: &'a Cell<usize>,
+    weak: &'a Cell<usize>,
+}
+
+impl<T: ?Sized> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        if let Some(inner) = self.inner() {
+            inc_count(inner.weak);
+        }
+
+        Weak { ptr: self.ptr }
+    }
+}
+
+impl<T: ?Sized> Drop for Weak<T> {
+    fn drop(&mut self) {
+        let Some(inner) = self.inner() else {
+            return;
+        };
+        let weak = inner.weak.get() - 1;
+        inner.weak.set(weak);
+        if weak > 0 {
+            return;
+        }
+
+        // SAFETY: the strong references share a weak one, so the value has
+        // already been dropped, and nobody is left to look at the counters.
+        // Every `RcBox` comes from the global allocator, with a layout equal
+        // to `Layout::for_value` of it: through `Box`, or `copy_from_ptr`.
+        unsafe {
+            let layout = Layout::for_value(self.ptr.as_ref());
+            alloc::dealloc(self.ptr.as_ptr().cast(), layout);
+        }
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(Weak)")
+    }
+}
 
 #[derive(Debug)]
 enum List {
@@ -55,7 +691,6 @@ enum List {
 }
 use self::List::{Cons, Nil};
 
-#[cfg(feature = "skip")]
 #[test]
 fn rc_test1() {
     let a = Rc::new(Cons(5, Rc::new(Cons(10, Rc::new(Nil)))));
@@ -67,7 +702,6 @@ fn rc_test1() {
     println!("c: {:?}", c);
 }
 
-#[cfg(feature = "skip")]
 #[test]
 fn rc_test2() {
     let a = Rc::new(Cons(5, Rc::new(Cons(10, Rc::new(Nil)))));
@@ -86,7 +720,6 @@ fn rc_test2() {
     assert_eq!(Rc::strong_count(&a), 2);
 }
 
-#[cfg(feature = "skip")]
 #[test]
 fn rc_test3() {
     let rc = Rc::new(RefCell::new(String::from("hello")));
@@ -95,6 +728,367 @@ fn rc_test3() {
     assert_eq!(rc.borrow().as_str(), "hello, world");
 }
 
+#[test]
+fn rc_drops_value_once() {
+    #[derive(Debug)]
+    struct Droppable<'a>(&'a Cell<usize>);
+
+    impl Drop for Droppable<'_> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    let drops = Cell::new(0);
+    let a = Rc::new(Droppable(&drops));
+    let b = Rc::clone(&a);
+
+    drop(a);
+    assert_eq!(drops.get(), 0);
+    assert_eq!(Rc::strong_count(&b), 1);
+
+    drop(b);
+    assert_eq!(drops.get(), 1);
+}
+
+#[test]
+fn weak_test() {
+    let empty: Weak<i32> = Weak::new();
+    assert!(empty.upgrade().is_none());
+    assert_eq!(empty.strong_count(), 0);
+    assert!(empty.ptr_eq(&Weak::new()));
+
+    let a = Rc::new(String::from("hello"));
+    let w1 = Rc::downgrade(&a);
+    let w2 = w1.clone();
+    assert_eq!((Rc::strong_count(&a), Rc::weak_count(&a)), (1, 2));
+    assert!(w1.ptr_eq(&w2));
+
+    let b = w1.upgrade().unwrap();
+    assert_eq!(*b, "hello");
+    assert_eq!(w1.strong_count(), 2);
+
+    drop((a, b));
+    assert!(w1.upgrade().is_none());
+    assert_eq!(w2.strong_count(), 0);
+}
+
+#[test]
+fn rc_get_mut() {
+    let mut a = Rc::new(5);
+    *Rc::get_mut(&mut a).unwrap() += 1;
+
+    let b = Rc::clone(&a);
+    assert!(Rc::get_mut(&mut a).is_none());
+    drop(b);
+
+    let w = Rc::downgrade(&a);
+    assert!(Rc::get_mut(&mut a).is_none());
+    drop(w);
+    assert_eq!(*Rc::get_mut(&mut a).unwrap(), 6);
+}
+
+#[test]
+fn rc_make_mut() {
+    let mut config = Rc::new(String::from("v1"));
+    let snapshot = Rc::clone(&config);
+
+    Rc::make_mut(&mut config).push_str("+edit"); // clones
+    assert_eq!((config.as_str(), snapshot.as_str()), ("v1+edit", "v1"));
+    assert_eq!(Rc::strong_count(&snapshot), 1);
+
+    let weak = Rc::downgrade(&config);
+    Rc::make_mut(&mut config).push('!'); // moves, disassociating `weak`
+    assert_eq!(config.as_str(), "v1+edit!");
+    assert!(weak.upgrade().is_none());
+    assert_eq!(Rc::weak_count(&config), 0);
+}
+
+#[test]
+fn rc_try_unwrap() {
+    let a = Rc::new(String::from("hello"));
+    let b = Rc::clone(&a);
+
+    let a = Rc::try_unwrap(a).unwrap_err();
+    assert_eq!(Rc::unwrap_or_clone(b), "hello");
+
+    let weak = Rc::downgrade(&a);
+    assert_eq!(Rc::into_inner(a), Some(String::from("hello")));
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn rc_into_raw() {
+    let a = Rc::new(String::from("Hello"));
+    let b = Rc::clone(&a);
+
+    let ptr: *const String = Rc::into_raw(a); // a moved
+    assert_eq!(ptr, Rc::as_ptr(&b));
+    println!("ptr = {:?}", unsafe { &*ptr });
+
+    unsafe {
+        let a = Rc::from_raw(ptr);
+        assert!(Rc::ptr_eq(&a, &b));
+        assert_eq!(Rc::strong_count(&a), 2);
+    }
+    assert_eq!(Rc::strong_count(&b), 1);
+}
+
+#[test]
+fn rc_through_void_ptr_context() {
+    use std::ffi::c_void;
+
+    type Log = RefCell<Vec<&'static str>>;
+
+    // A C-style API that hands an opaque context back to a callback.
+    fn register(ctx: *const c_void, callback: fn(*const c_void)) {
+        callback(ctx);
+        callback(ctx);
+    }
+
+    fn on_event(ctx: *const c_void) {
+        // borrow the handle: take a strong reference for the duration of the call
+        let ctx = ctx as *const Log;
+        unsafe { Rc::increment_strong_count(ctx) };
+        let log = unsafe { Rc::from_raw(ctx) };
+        log.borrow_mut().push("event");
+    }
+
+    let log: Rc<Log> = Rc::new(RefCell::new(Vec::new()));
+    let ctx = Rc::into_raw(Rc::clone(&log)) as *const c_void;
+    register(ctx, on_event);
+
+    assert_eq!(Rc::strong_count(&log), 2);
+    unsafe { Rc::decrement_strong_count(ctx as *const Log) };
+    assert_eq!(Rc::strong_count(&log), 1);
+    assert_eq!(*log.borrow(), ["event", "event"]);
+}
+
+#[test]
+fn rc_unsized() {
+    let slice: Rc<[String]> = Rc::from(vec![String::from("a"), String::from("b")]);
+    assert_eq!(slice.len(), 2);
+    assert_eq!(slice[1], "b");
+
+    let table: Rc<str> = Rc::from("string table");
+    let copy = Rc::clone(&table);
+    assert_eq!(&*copy, "string table");
+    assert_eq!(&*Rc::<str>::from(String::from("owned")), "owned");
+
+    let raw = Rc::into_raw(table);
+    let table = unsafe { Rc::from_raw(raw) };
+    assert!(Rc::ptr_eq(&table, &copy));
+    assert_eq!(Rc::strong_count(&table), 2);
+
+    let weak = Rc::downgrade(&slice);
+    drop(slice);
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn rc_trait_object() {
+    trait Animal: fmt::Debug {
+        fn sound(&self) -> String;
+    }
+
+    #[derive(Debug)]
+    struct Dog(String);
+
+    #[derive(Debug)]
+    struct Cat;
+
+    impl Animal for Dog {
+        fn sound(&self) -> String {
+            format!("{}: woof", self.0)
+        }
+    }
+
+    impl Animal for Cat {
+        fn sound(&self) -> String {
+            String::from("meow")
+        }
+    }
+
+    let animals: Vec<Rc<dyn Animal>> = vec![
+        Rc::from(Box::new(Dog(String::from("rex"))) as Box<dyn Animal>),
+        Rc::from(Box::new(Cat) as Box<dyn Animal>),
+    ];
+    let plugin = Rc::clone(&animals[0]);
+
+    assert_eq!(plugin.sound(), "rex: woof");
+    assert_eq!(animals[1].sound(), "meow");
+    assert_eq!(Rc::strong_count(&plugin), 2);
+}
+
+#[test]
+fn rc_without_debug() {
+    let greet: Rc<dyn Fn(&str) -> String> =
+        Rc::from(Box::new(|name: &str| format!("Hello, {name}!")) as Box<dyn Fn(&str) -> String>);
+    let handler = Rc::clone(&greet);
+
+    assert_eq!(handler("Rust"), "Hello, Rust!");
+    assert_eq!(format!("{:p}", handler), format!("{:p}", greet));
+}
+
+#[test]
+fn rc_as_map_key() {
+    use std::collections::{BTreeMap, HashMap};
+
+    let apple: Rc<str> = Rc::from("apple");
+    let mut counts = HashMap::new();
+    counts.insert(Rc::clone(&apple), 3);
+    counts.insert(Rc::from("kiwi"), 1);
+    assert_eq!(counts.get("apple"), Some(&3));
+    assert_eq!(counts[&Rc::from("kiwi")], 1);
+
+    let sorted: BTreeMap<Rc<i32>, &str> = [(Rc::new(2), "two"), (Rc::from(1), "one")].into();
+    assert_eq!(sorted.values().copied().collect::<Vec<_>>(), ["one", "two"]);
+
+    assert_eq!(Rc::new(5), Rc::new(5));
+    assert!(Rc::new(4) < Rc::new(5));
+    assert_eq!(format!("{}", apple), "apple");
+    assert_eq!(*Rc::<Vec<i32>>::default(), []);
+    assert_eq!(AsRef::<str>::as_ref(&apple), "apple");
+}
+
+#[test]
+fn rc_new_cyclic() {
+    #[derive(Debug)]
+    struct Gadget {
+        name: &'static str,
+        me: Weak<Gadget>,
+    }
+
+    let gadget = Rc::new_cyclic(|me| {
+        assert!(me.upgrade().is_none());
+        Gadget {
+            name: "gadget",
+            me: me.clone(),
+        }
+    });
+
+    let me = gadget.me.upgrade().unwrap();
+    assert!(Rc::ptr_eq(&me, &gadget));
+    assert_eq!(me.name, "gadget");
+    assert_eq!((Rc::strong_count(&gadget), Rc::weak_count(&gadget)), (2, 1));
+
+    drop(me);
+    let weak = Rc::downgrade(&gadget);
+    drop(gadget);
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn unique_rc() {
+    let mut unique = UniqueRc::new(vec![1, 2]);
+    let weak = UniqueRc::downgrade(&unique);
+    assert!(weak.upgrade().is_none());
+
+    unique.push(3);
+    let rc = UniqueRc::into_rc(unique);
+    assert_eq!(*weak.upgrade().unwrap(), [1, 2, 3]);
+    assert_eq!((Rc::strong_count(&rc), Rc::weak_count(&rc)), (1, 1));
+
+    let never_shared = UniqueRc::new(String::from("dropped"));
+    let weak = UniqueRc::downgrade(&never_shared);
+    drop(never_shared);
+    assert!(weak.upgrade().is_none());
+}
+
+mod custom_rc_demo {
+    use super::{Rc, UniqueRc, Weak};
+    use crate::delim;
+    use crate::s4_refcell::RefCell;
+
+    #[derive(Debug)]
+    struct Node {
+        value: i32,
+        next: Option<Rc<RefCell<Node>>>,
+        head: Option<Weak<RefCell<Node>>>,
+    }
+
+    impl Drop for Node {
+        fn drop(&mut self) {
+            println!("Dropping {}", self.value);
+        }
+    }
+
+    /**
+     * The `std_rc_demo::rc_weak_demo` list, built with our own `Rc` and `Weak`.
+     * The back pointer from `a` to `c` is weak, so dropping `c` frees the list.
+     */
+    #[rustfmt::skip]
+    #[test]
+    fn rc_weak_demo() {
+        // c ---> b ---> a
+        let a = Rc::new(RefCell::new(Node { value: 1, next: None, head: None, }));
+        let b = Rc::new(RefCell::new(Node { value: 2, next: Some(Rc::clone(&a)), head: None, }));
+        let c = Rc::new(RefCell::new(Node { value: 3, next: Some(Rc::clone(&b)), head: None, }));
+
+        // c --> b --> a
+        // ^           |
+        // +-----------+
+        a.borrow_mut().head = Some(Rc::downgrade(&c));
+        println!("a strong count: {:?}, weak count: {:?}", Rc::strong_count(&a), Rc::weak_count(&a));
+        println!("b strong count: {:?}, weak count: {:?}", Rc::strong_count(&b), Rc::weak_count(&b));
+        println!("c strong count: {:?}, weak count: {:?}", Rc::strong_count(&c), Rc::weak_count(&c));
+        delim!();
+        assert_eq!((Rc::strong_count(&a), Rc::weak_count(&a)), (2, 0));
+        assert_eq!((Rc::strong_count(&c), Rc::weak_count(&c)), (1, 1));
+
+        let head = a.borrow().head.as_ref().unwrap().upgrade().unwrap();
+        assert_eq!(head.borrow().value, 3);
+        drop(head);
+
+        let weak_a = Rc::downgrade(&a);
+        drop((a, b, c)); // Dropping 3, 2, 1
+        assert!(weak_a.upgrade().is_none());
+    }
+
+    #[derive(Debug)]
+    struct Link {
+        value: i32,
+        next: Option<Rc<Link>>,
+        head: Weak<Link>,
+    }
+
+    impl Drop for Link {
+        fn drop(&mut self) {
+            println!("Dropping {}", self.value);
+        }
+    }
+
+    /**
+     * The same list, built with a `UniqueRc`. `c` hands out its `Weak` before it
+     * is shared, so neither a `RefCell` nor an `Option` around the back pointer
+     * is needed to close the loop.
+     */
+    #[rustfmt::skip]
+    #[test]
+    fn unique_rc_demo() {
+        let mut c = UniqueRc::new(Link { value: 3, next: None, head: Weak::new(), });
+        let head = UniqueRc::downgrade(&c);
+
+        // c ---> b ---> a
+        // ^             |
+        // +-------------+
+        let a = Rc::new(Link { value: 1, next: None, head: head.clone(), });
+        let b = Rc::new(Link { value: 2, next: Some(a), head: head.clone(), });
+        c.next = Some(b);
+        c.head = head;
+        let c = UniqueRc::into_rc(c);
+
+        let a = c.next.as_ref().unwrap().next.as_ref().unwrap();
+        assert!(Rc::ptr_eq(&a.head.upgrade().unwrap(), &c));
+        println!("c strong count: {:?}, weak count: {:?}", Rc::strong_count(&c), Rc::weak_count(&c));
+        assert_eq!((Rc::strong_count(&c), Rc::weak_count(&c)), (1, 3));
+
+        let weak_c = Rc::downgrade(&c);
+        drop(c); // Dropping 3, 2, 1
+        assert!(weak_c.upgrade().is_none());
+    }
+}
+
 mod std_rc_demo {
     use crate::delim;
     use std::cell::RefCell;
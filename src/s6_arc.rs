@@ -0,0 +1,659 @@
+use std::{
+    alloc::{self, Layout},
+    borrow, cmp, fmt,
+    hash::{Hash, Hasher},
+    hint,
+    marker::PhantomData,
+    mem,
+    ops::Deref,
+    process,
+    ptr::{self, NonNull},
+    sync::atomic::{self, AtomicUsize, Ordering},
+};
+
+/// Counts above this abort the process: `mem::forget`ting clones in a loop
+/// could otherwise overflow a counter and free the value while in use.
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+
+/// The value of `ArcInner::weak` while `Arc::is_unique` looks at the counts.
+const WEAK_LOCKED: usize = usize::MAX;
+
+struct ArcInner<T: ?Sized> {
+    strong: AtomicUsize,
+    // All strong references together own one weak reference, which keeps the
+    // allocation alive while the value is.
+    weak: AtomicUsize,
+    value: T,
+}
+
+/**
+ * Arc<T>, the Atomically Reference Counted Smart Pointer
+ *
+ * `Arc<T>` is the thread-safe counterpart of `Rc<T>`: it keeps its reference
+ * counts in atomics, so clones of it can be sent to and dropped on other
+ * threads. The value itself is only shared, never mutated through an `Arc`,
+ * so to change it from several threads, put it in a `RwCell` or a `Mutex`.
+ *
+ * Atomic operations cost more than plain ones, which is why `Rc<T>` still
+ * exists for single-threaded code.
+ */
+struct Arc<T: ?Sized> {
+    ptr: NonNull<ArcInner<T>>,
+    // `Arc<T>` owns an `ArcInner<T>` as far as the drop checker is concerned.
+    _phantom: PhantomData<ArcInner<T>>,
+}
+
+// Sending an `Arc` to another thread shares the value with it, and whichever
+// thread drops the last `Arc` drops the value, so `T` must be both.
+unsafe impl<T: ?Sized + Send + Sync> Send for Arc<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for Arc<T> {}
+
+impl<T> Arc<T> {
+    fn new(value: T) -> Self {
+        let boxed = Box::new(ArcInner {
+            strong: AtomicUsize::new(1),
+            weak: AtomicUsize::new(1),
+            value,
+        });
+
+        Arc {
+            // SAFETY: `Box::into_raw` never returns a null pointer.
+            ptr: unsafe { NonNull::new_unchecked(Box::into_raw(boxed)) },
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns a mutable reference to the value, cloning it into a new
+    /// allocation first if other `Arc`s share it (clone-on-write).
+    ///
+    /// If only `Weak`s share it, the value is moved into a new allocation
+    /// instead, and those `Weak`s will no longer upgrade.
+    fn make_mut(this: &mut Self) -> &mut T
+    where
+        T: Clone,
+    {
+        let inner = this.inner();
+        // Give up our strong reference for now, so that no `Weak` can
+        // upgrade while we look at the weak count.
+        if inner
+            .strong
+            .compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            *this = Arc::new((**this).clone());
+        } else if inner.weak.load(Ordering::Relaxed) != 1 {
+            // SAFETY: we were the only strong reference and have given it up,
+            // so the value is moved out exactly once and the `Weak`s see it
+            // as dropped.
+            let value = unsafe { ptr::read(&inner.value) };
+            let old = mem::replace(this, Arc::new(value));
+            drop(Weak { ptr: old.ptr });
+            mem::forget(old);
+        } else {
+            inner.strong.store(1, Ordering::Release);
+        }
+
+        Arc::get_mut(this).expect("`this` was made unique above")
+    }
+
+    /// Returns the value if this is the only `Arc` to it, or gives the `Arc`
+    /// back otherwise. Outstanding `Weak`s will no longer upgrade.
+    fn try_unwrap(this: Self) -> Result<T, Self> {
+        if this
+            .inner()
+            .strong
+            .compare_exchange(1, 0, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(this);
+        }
+        // Synchronize with the `Release` decrements of the other `Arc`s, so
+        // that their uses of the value happen before we take it.
+        atomic::fence(Ordering::Acquire);
+
+        let this = mem::ManuallyDrop::new(this);
+        // SAFETY: we were the only strong reference and have given it up,
+        // so the value is moved out exactly once.
+        let value = unsafe { ptr::read(&this.inner().value) };
+        drop(Weak { ptr: this.ptr });
+        Ok(value)
+    }
+
+    /// Returns the value if this is the only `Arc` to it, and drops the `Arc`
+    /// otherwise.
+    ///
+    /// Unlike `Arc::try_unwrap(this).ok()`, this hands the value to exactly
+    /// one of several threads dropping their `Arc`s at the same time.
+    fn into_inner(this: Self) -> Option<T> {
+        let this = mem::ManuallyDrop::new(this);
+        if this.inner().strong.fetch_sub(1, Ordering::Release) != 1 {
+            return None;
+        }
+        atomic::fence(Ordering::Acquire);
+
+        // SAFETY: we were the last strong reference, so the value is moved
+        // out exactly once.
+        let value = unsafe { ptr::read(&this.inner().value) };
+        drop(Weak { ptr: this.ptr });
+        Some(value)
+    }
+}
+
+impl<T: ?Sized> Arc<T> {
+    /// Returns the number of `Arc`s. Other threads may change it at any time,
+    /// so this is only a snapshot.
+    fn strong_count(this: &Self) -> usize {
+        this.inner().strong.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of `Weak`s. Other threads may change it at any time,
+    /// so this is only a snapshot.
+    fn weak_count(this: &Self) -> usize {
+        match this.inner().weak.load(Ordering::Relaxed) {
+            // `is_unique` only locks the count while there are no `Weak`s.
+            WEAK_LOCKED => 0,
+            n => n - 1,
+        }
+    }
+
+    /// Creates a `Weak` pointer to this allocation.
+    fn downgrade(this: &Self) -> Weak<T> {
+        let weak = &this.inner().weak;
+        let mut n = weak.load(Ordering::Relaxed);
+        loop {
+            if n == WEAK_LOCKED {
+                hint::spin_loop();
+                n = weak.load(Ordering::Relaxed);
+                continue;
+            }
+            if n > MAX_REFCOUNT {
+                process::abort();
+            }
+
+            match weak.compare_exchange_weak(n, n + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return Weak { ptr: this.ptr },
+                Err(actual) => n = actual,
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the value if there are no other `Arc`
+    /// or `Weak` pointers to it.
+    fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if Arc::is_unique(this) {
+            // SAFETY: nobody else can reach the value.
+            Some(unsafe { &mut (*this.ptr.as_ptr()).value })
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if both `Arc`s point to the same allocation.
+    fn ptr_eq(this: &Self, other: &Self) -> bool {
+        ptr::eq(this.ptr.as_ptr(), other.ptr.as_ptr())
+    }
+
+    fn is_unique(this: &mut Self) -> bool {
+        let inner = this.inner();
+        // Lock the weak count while it says there are no `Weak`s, so that
+        // no other thread can `downgrade` one of the `Arc`s we count next.
+        if inner
+            .weak
+            .compare_exchange(1, WEAK_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+
+        // `Acquire` pairs with the `Release` decrement of an `Arc` dropped on
+        // another thread, whose uses of the value must happen before ours.
+        let unique = inner.strong.load(Ordering::Acquire) == 1;
+        inner.weak.store(1, Ordering::Release);
+        unique
+    }
+
+    fn inner(&self) -> &ArcInner<T> {
+        // SAFETY: the `ArcInner` stays allocated as long as a strong reference
+        // to it exists, and we are one.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: ?Sized> Clone for Arc<T> {
+    fn clone(&self) -> Self {
+        // `Relaxed` is enough: we already hold a reference, so the count can't
+        // drop to zero under us, and no other memory needs to be synchronized.
+        if self.inner().strong.fetch_add(1, Ordering::Relaxed) > MAX_REFCOUNT {
+            process::abort();
+        }
+
+        Arc {
+            ptr: self.ptr,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for Arc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner().value
+    }
+}
+
+impl<T: ?Sized> Drop for Arc<T> {
+    fn drop(&mut self) {
+        // `Release` publishes our uses of the value to whichever thread drops
+        // the last `Arc`, and that thread's `Acquire` fence receives them.
+        if self.inner().strong.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        atomic::fence(Ordering::Acquire);
+
+        // SAFETY: we were the last strong reference, so nobody can reach the
+        // value any more. `Weak::upgrade` fails from now on, so it is dropped
+        // exactly once, here.
+        unsafe { ptr::drop_in_place(&mut (*self.ptr.as_ptr()).value) };
+
+        // Give up the weak reference that all strong references shared, which
+        // frees the allocation unless other `Weak`s are left.
+        drop(Weak { ptr: self.ptr });
+    }
+}
+
+impl<T: fmt::Debug + ?Sized> fmt::Debug for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Display + ?Sized> fmt::Display for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+/// Formats the address of the value, like `{:p}` does for references.
+impl<T: ?Sized> fmt::Pointer for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&ptr::addr_of!(**self), f)
+    }
+}
+
+// Comparisons and hashing look at the values, not at the allocations; use
+// `Arc::ptr_eq` to tell whether two `Arc`s share one.
+
+impl<T: PartialEq + ?Sized> PartialEq for Arc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Eq + ?Sized> Eq for Arc<T> {}
+
+impl<T: PartialOrd + ?Sized> PartialOrd for Arc<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<T: Ord + ?Sized> Ord for Arc<T> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: Hash + ?Sized> Hash for Arc<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl<T: ?Sized> borrow::Borrow<T> for Arc<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized> AsRef<T> for Arc<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T: Default> Default for Arc<T> {
+    fn default() -> Self {
+        Arc::new(T::default())
+    }
+}
+
+impl<T> From<T> for Arc<T> {
+    fn from(value: T) -> Self {
+        Arc::new(value)
+    }
+}
+
+/**
+ * Weak<T>, a non-owning reference to an `Arc<T>` allocation
+ *
+ * Like `s5_rc::Weak<T>`, but its counters are atomic, so it can be upgraded
+ * on one thread while the last `Arc<T>` is dropped on another: exactly one of
+ * them wins, and `upgrade` never revives a value that is being dropped.
+ */
+struct Weak<T: ?Sized> {
+    // Dangling, and never dereferenced, for a `Weak` made by `Weak::new`.
+    ptr: NonNull<ArcInner<T>>,
+}
+
+// A `Weak` can be upgraded to an `Arc` on the thread it was sent to.
+unsafe impl<T: ?Sized + Send + Sync> Send for Weak<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for Weak<T> {}
+
+impl<T> Weak<T> {
+    /// Creates a `Weak` that never upgrades, without allocating.
+    fn new() -> Self {
+        Weak {
+            ptr: NonNull::new(ptr::without_provenance_mut(usize::MAX))
+                .expect("usize::MAX is not null"),
+        }
+    }
+}
+
+impl<T: ?Sized> Weak<T> {
+    /// Attempts to get an `Arc` to the value, or `None` if it has been dropped.
+    fn upgrade(&self) -> Option<Arc<T>> {
+        let strong = self.inner()?.strong;
+        let mut n = strong.load(Ordering::Relaxed);
+        loop {
+            // Once the count has reached zero the value is being dropped, and
+            // it must stay at zero, so we can't just `fetch_add` here.
+            if n == 0 {
+                return None;
+            }
+            if n > MAX_REFCOUNT {
+                process::abort();
+            }
+
+            match strong.compare_exchange_weak(n, n + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => {
+                    return Some(Arc {
+                        ptr: self.ptr,
+                        _phantom: PhantomData,
+                    })
+                }
+                Err(actual) => n = actual,
+            }
+        }
+    }
+
+    /// Returns the number of `Arc`s, which is only a snapshot.
+    fn strong_count(&self) -> usize {
+        self.inner()
+            .map_or(0, |inner| inner.strong.load(Ordering::Relaxed))
+    }
+
+    /// Returns `true` if both `Weak`s point to the same allocation, or if
+    /// both were made by `Weak::new`.
+    fn ptr_eq(&self, other: &Self) -> bool {
+        ptr::eq(self.ptr.as_ptr(), other.ptr.as_ptr())
+    }
+
+    fn is_dangling(&self) -> bool {
+        self.ptr.as_ptr() as *mut () as usize == usize::MAX
+    }
+
+    /// Returns the counters, or `None` if there is no allocation.
+    ///
+    /// Unlike `Arc::inner`, this never hands out a reference to the value,
+    /// which may already have been dropped.
+    fn inner(&self) -> Option<WeakInner<'_>> {
+        if self.is_dangling() {
+            return None;
+        }
+
+        // SAFETY: the allocation stays alive as long as a weak reference to it
+        // exists, and we are one. We only create references to the counters.
+        let ptr = self.ptr.as_ptr();
+        unsafe {
+            Some(WeakInner {
+                strong: &*ptr::addr_of!((*ptr).strong),
+                weak: &*ptr::addr_of!((*ptr).weak),
+            })
+        }
+    }
+}
+
+/// The counters of an `ArcInner`, borrowed through a `Weak`.
+struct WeakInner<'a> {
+    strong: &'a AtomicUsize,
+    weak: &'a AtomicUsize,
+}
+
+impl<T: ?Sized> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        if let Some(inner) = self.inner() {
+            // We are a `Weak`, so `Arc::is_unique` can't have locked the count.
+            if inner.weak.fetch_add(1, Ordering::Relaxed) > MAX_REFCOUNT {
+                process::abort();
+            }
+        }
+
+        Weak { ptr: self.ptr }
+    }
+}
+
+impl<T: ?Sized> Drop for Weak<T> {
+    fn drop(&mut self) {
+        let Some(inner) = self.inner() else {
+            return;
+        };
+        if inner.weak.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        atomic::fence(Ordering::Acquire);
+
+        // SAFETY: the strong references share a weak one, so the value has
+        // already been dropped, and nobody is left to look at the counters.
+        // The memory was allocated by `Box` with this layout.
+        unsafe {
+            let layout = Layout::for_value(self.ptr.as_ref());
+            alloc::dealloc(self.ptr.as_ptr().cast(), layout);
+        }
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(Weak)")
+    }
+}
+
+#[test]
+fn arc_test() {
+    let a = Arc::new(String::from("shared"));
+    let b = Arc::clone(&a);
+    assert_eq!(Arc::strong_count(&a), 2);
+    assert!(Arc::ptr_eq(&a, &b));
+    assert_eq!(*b, "shared");
+
+    drop(b);
+    assert_eq!(Arc::strong_count(&a), 1);
+}
+
+#[test]
+fn arc_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<Arc<String>>();
+    assert_send_sync::<Weak<Vec<i32>>>();
+}
+
+#[test]
+fn arc_drops_value_once() {
+    use std::thread;
+
+    struct Droppable<'a>(&'a AtomicUsize);
+
+    impl Drop for Droppable<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let drops = AtomicUsize::new(0);
+    let a = Arc::new(Droppable(&drops));
+    thread::scope(|s| {
+        for _ in 0..4 {
+            let a = Arc::clone(&a);
+            s.spawn(move || {
+                let clones: Vec<_> = (0..100).map(|_| Arc::clone(&a)).collect();
+                drop(clones);
+            });
+        }
+    });
+    assert_eq!(drops.load(Ordering::Relaxed), 0);
+
+    drop(a);
+    assert_eq!(drops.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn weak_test() {
+    let a = Arc::new(5);
+    let weak = Arc::downgrade(&a);
+    assert_eq!((Arc::strong_count(&a), Arc::weak_count(&a)), (1, 1));
+    assert_eq!(*weak.upgrade().unwrap(), 5);
+
+    drop(a);
+    assert!(weak.upgrade().is_none());
+    assert_eq!(weak.strong_count(), 0);
+    assert!(Weak::<i32>::new().upgrade().is_none());
+}
+
+#[test]
+fn weak_upgrade_races_drop() {
+    use std::thread;
+
+    for _ in 0..100 {
+        let a = Arc::new(String::from("racy"));
+        let weak = Arc::downgrade(&a);
+
+        let upgraded = thread::scope(|s| {
+            let upgrader = s.spawn(|| weak.upgrade().map(|a| a.len()));
+            drop(a);
+            upgrader.join().unwrap()
+        });
+
+        assert!(upgraded.is_none() || upgraded == Some(4));
+        assert!(weak.upgrade().is_none());
+    }
+}
+
+#[test]
+fn arc_get_mut() {
+    let mut a = Arc::new(1);
+    *Arc::get_mut(&mut a).unwrap() += 1;
+
+    let weak = Arc::downgrade(&a);
+    assert!(Arc::get_mut(&mut a).is_none());
+    drop(weak);
+
+    let b = Arc::clone(&a);
+    assert!(Arc::get_mut(&mut a).is_none());
+    drop(b);
+    assert_eq!(Arc::get_mut(&mut a), Some(&mut 2));
+}
+
+#[test]
+fn arc_make_mut() {
+    let mut a = Arc::new(vec![1]);
+    let b = Arc::clone(&a);
+    Arc::make_mut(&mut a).push(2);
+    assert_eq!((&*a, &*b), (&vec![1, 2], &vec![1]));
+
+    let weak = Arc::downgrade(&a);
+    Arc::make_mut(&mut a).push(3);
+    assert!(weak.upgrade().is_none());
+
+    let ptr = ptr::addr_of!(*a);
+    Arc::make_mut(&mut a).push(4);
+    assert_eq!(ptr::addr_of!(*a), ptr);
+    assert_eq!(*a, [1, 2, 3, 4]);
+}
+
+#[test]
+fn arc_try_unwrap() {
+    let a = Arc::new(String::from("only"));
+    let b = Arc::clone(&a);
+    let a = Arc::try_unwrap(a).unwrap_err();
+    drop(b);
+
+    let weak = Arc::downgrade(&a);
+    assert_eq!(Arc::try_unwrap(a).unwrap(), "only");
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn arc_into_inner() {
+    use std::thread;
+
+    for _ in 0..100 {
+        let a = Arc::new(vec![1, 2, 3]);
+        let b = Arc::clone(&a);
+
+        let (x, y) = thread::scope(|s| {
+            let x = s.spawn(move || Arc::into_inner(a));
+            let y = s.spawn(move || Arc::into_inner(b));
+            (x.join().unwrap(), y.join().unwrap())
+        });
+
+        assert_eq!(x.is_some() as usize + y.is_some() as usize, 1);
+    }
+}
+
+/**
+ * The worker pool pattern that `Rc<T>` can't do: each worker thread holds
+ * an `Arc` to the same state, and the state is dropped with the last one.
+ */
+mod worker_pool_demo {
+    use super::Arc;
+    use crate::delim;
+    use crate::s4_refcell::RwCell;
+    use std::thread;
+
+    #[derive(Debug, Default)]
+    struct Stats {
+        jobs: usize,
+        total: u64,
+    }
+
+    #[test]
+    fn shared_state_demo() {
+        let stats = Arc::new(RwCell::new(Stats::default()));
+
+        let workers: Vec<_> = (1..=4u64)
+            .map(|id| {
+                let stats = Arc::clone(&stats);
+                thread::spawn(move || {
+                    for job in 0..10 {
+                        let mut stats = stats.write().unwrap();
+                        stats.jobs += 1;
+                        stats.total += id * job;
+                    }
+                })
+            })
+            .collect();
+        println!("strong count while working: {}", Arc::strong_count(&stats));
+        delim!();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(Arc::strong_count(&stats), 1);
+
+        let stats = Arc::into_inner(stats).unwrap().into_inner().unwrap();
+        println!("{:?}", stats);
+        assert_eq!((stats.jobs, stats.total), (40, 450));
+    }
+}